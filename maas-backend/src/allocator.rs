@@ -0,0 +1,734 @@
+//! Storage behind the service.
+//!
+//! Handlers only talk to the [`Allocator`] trait, so another allocation
+//! strategy can be plugged in through `AppState::with_allocator` without
+//! touching them. Metrics, events and logging live in the handlers and apply
+//! whichever allocator is in use. [`BufferAllocator`] is the default.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::{Mutex, TryLockError};
+use std::time::{Duration, SystemTime};
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use memmap2::MmapMut;
+use prometheus::register_counter;
+use tracing::error;
+use uuid::Uuid;
+use crate::config::{Backing, MemoryConfig};
+use crate::models::{AllocateRequest, AllocationInfo, AllocationRecord, AppError, MemoryStats};
+use crate::state::lock;
+
+lazy_static::lazy_static! {
+    static ref INTEGRITY_FAILURES: prometheus::Counter = register_counter!("maas_integrity_failures_total", "Reads whose data did not match the stored checksum").unwrap();
+}
+
+/// Outcome of releasing one reference to an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deallocation {
+    /// That was the last reference; the buffer is gone.
+    Freed { size_bytes: usize },
+    /// Other holders remain, so the buffer was kept.
+    Released { ref_count: usize },
+}
+
+pub trait Allocator {
+    /// Creates a zero-filled allocation sized by `request`.
+    fn allocate(&self, request: &AllocateRequest) -> Result<AllocationInfo, AppError>;
+
+    /// Creates an allocation holding `bytes`. An implementation may hand
+    /// out an existing allocation with identical contents instead, in which
+    /// case the returned `ref_count` is above 1.
+    fn allocate_with_data(&self, bytes: &[u8]) -> Result<AllocationInfo, AppError>;
+
+    /// Releases one reference to an allocation, freeing it when none remain.
+    fn deallocate(&self, id: Uuid) -> Result<Deallocation, AppError>;
+
+    /// Copies `len` bytes starting at `offset` out of an allocation.
+    fn read(&self, id: Uuid, offset: usize, len: usize) -> Result<Vec<u8>, AppError>;
+
+    /// Copies `bytes` into an allocation starting at `offset`.
+    fn write(&self, id: Uuid, offset: usize, bytes: &[u8]) -> Result<(), AppError> {
+        self.write_chunk(id, offset, bytes)?;
+        self.finish_write(id)
+    }
+
+    /// Copies one chunk of a streamed write. Bookkeeping that would cost a
+    /// pass over the whole buffer per chunk may wait for `finish_write`.
+    fn write_chunk(&self, id: Uuid, offset: usize, bytes: &[u8]) -> Result<(), AppError>;
+
+    /// Ends a streamed write, counting it as one access.
+    fn finish_write(&self, id: Uuid) -> Result<(), AppError>;
+
+    fn size_of(&self, id: Uuid) -> Result<usize, AppError>;
+
+    fn allocations(&self) -> Vec<AllocationInfo>;
+
+    /// Frees every allocation at once, returning what was freed.
+    fn flush(&self) -> Vec<AllocationInfo>;
+
+    /// Full records of every allocation, with contents if `include_data`.
+    fn export(&self, include_data: bool) -> Vec<AllocationRecord>;
+
+    /// Recreates exported allocations under their original ids. Fails with
+    /// `409` while any allocation exists unless `replace` is set, in which
+    /// case the existing ones are freed and returned. Records that fail
+    /// `validate_records` are refused with `400` before anything changes.
+    fn import(&self, records: Vec<AllocationRecord>, replace: bool) -> Result<Vec<AllocationInfo>, AppError>;
+
+    fn get_active_allocations(&self) -> usize {
+        self.allocations().len()
+    }
+
+    fn get_total_allocated(&self) -> usize {
+        self.allocations().iter().map(|info| info.size_bytes).sum()
+    }
+
+    fn get_stats(&self) -> MemoryStats {
+        stats_from(self.allocations())
+    }
+
+    /// `(active allocations, allocated bytes)` if that can be read without
+    /// blocking, so it is safe to call from a panic hook.
+    fn try_snapshot(&self) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+fn stats_from(allocations: Vec<AllocationInfo>) -> MemoryStats {
+    let total_bytes = allocations.iter().map(|info| info.size_bytes).sum();
+    MemoryStats {
+        total_allocated_bytes: total_bytes,
+        total_allocated_mb: total_bytes as f64 / 1_048_576.0,
+        active_allocations: allocations.len(),
+        allocations,
+    }
+}
+
+/// `offset..offset + len`, if that lies within an allocation of `size_bytes`.
+pub fn checked_range(offset: usize, len: usize, size_bytes: usize) -> Result<Range<usize>, AppError> {
+    match offset.checked_add(len) {
+        Some(end) if end <= size_bytes => Ok(offset..end),
+        _ => Err(AppError(StatusCode::BAD_REQUEST, "Range exceeds allocation size".to_string())),
+    }
+}
+
+/// Checks every record on its own and that no id appears twice.
+pub fn validate_records(records: &[AllocationRecord]) -> Result<(), AppError> {
+    let mut ids = HashSet::new();
+    for record in records {
+        record.validate()?;
+        if !ids.insert(record.id) {
+            return Err(AppError(StatusCode::BAD_REQUEST, format!("Duplicate allocation id {}", record.id)));
+        }
+    }
+    Ok(())
+}
+
+fn not_found() -> AppError {
+    AppError(StatusCode::NOT_FOUND, "Allocation not found".to_string())
+}
+
+/// Buffer behind an allocation. Derefs to a byte slice so callers don't
+/// need to care which backing is in use.
+#[derive(Debug)]
+pub enum AllocationData {
+    Heap(Vec<u8>),
+    MmapAnon(MmapMut),
+}
+
+impl AllocationData {
+    pub fn new(backing: Backing, size_bytes: usize) -> std::io::Result<Self> {
+        match backing {
+            Backing::Heap => {
+                // `vec![0; n]` aborts the whole process when the allocation
+                // fails, so reserve fallibly first.
+                let mut vec = Vec::new();
+                vec.try_reserve_exact(size_bytes)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::OutOfMemory, e))?;
+                vec.resize(size_bytes, 0);
+                Ok(AllocationData::Heap(vec))
+            }
+            Backing::MmapAnon => MmapMut::map_anon(size_bytes).map(AllocationData::MmapAnon),
+        }
+    }
+}
+
+impl Deref for AllocationData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            AllocationData::Heap(vec) => vec,
+            AllocationData::MmapAnon(mmap) => mmap,
+        }
+    }
+}
+
+impl DerefMut for AllocationData {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            AllocationData::Heap(vec) => vec,
+            AllocationData::MmapAnon(mmap) => mmap,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryAllocation {
+    pub id: Uuid,
+    pub size_bytes: usize,
+    pub data: AllocationData,
+    pub created_at: SystemTime,
+    /// Number of reads and writes. Updated under the allocations lock that
+    /// the read/write path already holds, so tracking costs nothing extra.
+    pub access_count: u64,
+    pub last_accessed_at: Option<SystemTime>,
+    /// Number of clients holding this allocation. Only deduplicated
+    /// allocations go above 1; the buffer is freed when it reaches 0.
+    pub ref_count: usize,
+    /// Set while the allocation is registered in the dedup index.
+    pub content_hash: Option<ContentHash>,
+    /// Array shape the client allocated with, echoed back for validation.
+    pub element_size: Option<usize>,
+    pub element_count: Option<usize>,
+    /// CRC32 of `data`, kept only when `memory.integrity` is enabled.
+    pub checksum: Option<u32>,
+    /// Set while a streamed write has made `checksum` stale. Cleared by
+    /// `finish_write`, or by the next read if the write never finished.
+    pub rehash_pending: bool,
+}
+
+impl MemoryAllocation {
+    pub fn update_checksum(&mut self) {
+        self.checksum = Some(crc32fast::hash(&self.data));
+        self.rehash_pending = false;
+    }
+
+    /// False when a stored checksum no longer matches the buffer.
+    pub fn verify_checksum(&self) -> bool {
+        self.checksum.is_none_or(|checksum| crc32fast::hash(&self.data) == checksum)
+    }
+
+    pub fn record_access(&mut self) {
+        self.access_count += 1;
+        self.last_accessed_at = Some(SystemTime::now());
+    }
+
+    pub fn age(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.created_at).unwrap_or_default()
+    }
+
+    pub fn info(&self, now: SystemTime) -> AllocationInfo {
+        AllocationInfo {
+            id: self.id,
+            size_bytes: self.size_bytes,
+            size_mb: self.size_bytes as f64 / 1_048_576.0,
+            age_seconds: self.age(now).as_secs(),
+            access_count: self.access_count,
+            last_accessed_at: self.last_accessed_at.map(DateTime::<Utc>::from),
+            ref_count: self.ref_count,
+            element_size: self.element_size,
+            element_count: self.element_count,
+        }
+    }
+
+    pub fn record(&self, include_data: bool) -> AllocationRecord {
+        AllocationRecord {
+            id: self.id,
+            size_bytes: self.size_bytes,
+            created_at: self.created_at.into(),
+            access_count: self.access_count,
+            last_accessed_at: self.last_accessed_at.map(DateTime::<Utc>::from),
+            ref_count: self.ref_count,
+            element_size: self.element_size,
+            element_count: self.element_count,
+            data: include_data.then(|| self.data.to_vec()),
+        }
+    }
+}
+
+pub type ContentHash = u64;
+
+/// Hash used to find dedup candidates. Matches are always confirmed by
+/// comparing the bytes, so collisions never merge different contents.
+pub fn content_hash(bytes: &[u8]) -> ContentHash {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Default allocator: one dedicated buffer per allocation, on the heap or in
+/// an anonymous mapping depending on `memory.backing`. Also implements
+/// `memory.dedup` and `memory.integrity`.
+pub struct BufferAllocator {
+    allocations: Mutex<HashMap<Uuid, MemoryAllocation>>,
+    /// Content hash -> allocation holding that content, for `memory.dedup`.
+    /// Always locked after `allocations`, never on its own.
+    dedup_index: Mutex<HashMap<ContentHash, Uuid>>,
+    config: MemoryConfig,
+}
+
+impl BufferAllocator {
+    pub fn new(config: MemoryConfig) -> Self {
+        Self {
+            allocations: Mutex::new(HashMap::new()),
+            dedup_index: Mutex::new(HashMap::new()),
+            config,
+        }
+    }
+
+    /// A zeroed buffer of `size_bytes`, refused with `400` above
+    /// `memory.max_allocation_bytes`.
+    fn new_buffer(&self, size_bytes: usize) -> Result<AllocationData, AppError> {
+        if size_bytes > self.config.max_allocation_bytes {
+            return Err(AppError(
+                StatusCode::BAD_REQUEST,
+                format!("Allocation of {} bytes exceeds limit of {}", size_bytes, self.config.max_allocation_bytes),
+            ));
+        }
+        AllocationData::new(self.config.backing, size_bytes)
+            .map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to allocate memory: {}", e)))
+    }
+
+    fn new_allocation(
+        &self,
+        size_bytes: usize,
+        element_size: Option<usize>,
+        element_count: Option<usize>,
+    ) -> Result<MemoryAllocation, AppError> {
+        let data = self.new_buffer(size_bytes)?;
+        Ok(MemoryAllocation {
+            id: Uuid::new_v4(),
+            size_bytes,
+            data,
+            created_at: SystemTime::now(),
+            access_count: 0,
+            last_accessed_at: None,
+            ref_count: 1,
+            content_hash: None,
+            element_size,
+            element_count,
+            checksum: None,
+            rehash_pending: false,
+        })
+    }
+
+    fn with_allocation<T>(
+        &self,
+        id: Uuid,
+        f: impl FnOnce(&mut MemoryAllocation) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let mut allocations = lock(&self.allocations);
+        let allocation = allocations.get_mut(&id).ok_or_else(not_found)?;
+        f(allocation)
+    }
+
+    /// Drops an allocation from the dedup index, e.g. because its contents
+    /// changed or it was freed. Call with the allocations lock held.
+    fn forget_content(&self, allocation: &mut MemoryAllocation) {
+        if let Some(hash) = allocation.content_hash.take() {
+            let mut index = lock(&self.dedup_index);
+            if index.get(&hash) == Some(&allocation.id) {
+                index.remove(&hash);
+            }
+        }
+    }
+}
+
+impl Allocator for BufferAllocator {
+    fn allocate(&self, request: &AllocateRequest) -> Result<AllocationInfo, AppError> {
+        let size_bytes = request.resolve_size()?;
+        let mut allocation = self.new_allocation(size_bytes, request.element_size, request.element_count)?;
+        if self.config.integrity {
+            allocation.update_checksum();
+        }
+        let info = allocation.info(allocation.created_at);
+        lock(&self.allocations).insert(allocation.id, allocation);
+        Ok(info)
+    }
+
+    fn allocate_with_data(&self, bytes: &[u8]) -> Result<AllocationInfo, AppError> {
+        let hash = self.config.dedup.then(|| content_hash(bytes));
+
+        if let Some(hash) = hash {
+            let mut allocations = lock(&self.allocations);
+            let existing = lock(&self.dedup_index).get(&hash).copied();
+            if let Some(allocation) = existing.and_then(|id| allocations.get_mut(&id)) {
+                if *allocation.data == *bytes {
+                    allocation.ref_count += 1;
+                    return Ok(allocation.info(SystemTime::now()));
+                }
+            }
+        }
+
+        let mut allocation = self.new_allocation(bytes.len(), None, None)?;
+        allocation.data.copy_from_slice(bytes);
+        if self.config.integrity {
+            allocation.update_checksum();
+        }
+        let info = allocation.info(allocation.created_at);
+
+        let mut allocations = lock(&self.allocations);
+        if let Some(hash) = hash {
+            // A concurrent upload of the same bytes may have registered first;
+            // keep that one as the shared copy.
+            if let Entry::Vacant(entry) = lock(&self.dedup_index).entry(hash) {
+                entry.insert(allocation.id);
+                allocation.content_hash = Some(hash);
+            }
+        }
+        allocations.insert(allocation.id, allocation);
+        Ok(info)
+    }
+
+    fn deallocate(&self, id: Uuid) -> Result<Deallocation, AppError> {
+        let mut allocations = lock(&self.allocations);
+        let allocation = allocations.get_mut(&id).ok_or_else(not_found)?;
+        if allocation.ref_count > 1 {
+            allocation.ref_count -= 1;
+            return Ok(Deallocation::Released { ref_count: allocation.ref_count });
+        }
+
+        let mut removed = allocations.remove(&id).unwrap();
+        self.forget_content(&mut removed);
+        Ok(Deallocation::Freed { size_bytes: removed.size_bytes })
+    }
+
+    fn read(&self, id: Uuid, offset: usize, len: usize) -> Result<Vec<u8>, AppError> {
+        self.with_allocation(id, |allocation| {
+            let range = checked_range(offset, len, allocation.size_bytes)?;
+            if self.config.integrity && allocation.rehash_pending {
+                // The stored checksum predates a streamed write, so it can
+                // neither vouch for nor condemn the data. Adopt what is there.
+                allocation.update_checksum();
+            } else if self.config.integrity && !allocation.verify_checksum() {
+                INTEGRITY_FAILURES.inc();
+                error!(%id, size_bytes = allocation.size_bytes, "integrity check failed: data does not match stored checksum");
+                return Err(AppError(StatusCode::INTERNAL_SERVER_ERROR, "Integrity check failed".to_string()));
+            }
+            allocation.record_access();
+            Ok(allocation.data[range].to_vec())
+        })
+    }
+
+    fn write(&self, id: Uuid, offset: usize, bytes: &[u8]) -> Result<(), AppError> {
+        self.with_allocation(id, |allocation| {
+            let range = checked_range(offset, bytes.len(), allocation.size_bytes)?;
+            allocation.record_access();
+            self.forget_content(allocation);
+            allocation.data[range].copy_from_slice(bytes);
+            if self.config.integrity {
+                allocation.update_checksum();
+            }
+            Ok(())
+        })
+    }
+
+    fn write_chunk(&self, id: Uuid, offset: usize, bytes: &[u8]) -> Result<(), AppError> {
+        self.with_allocation(id, |allocation| {
+            let range = checked_range(offset, bytes.len(), allocation.size_bytes)?;
+            self.forget_content(allocation);
+            // Rehashing the whole buffer per chunk would be quadratic, so
+            // the checksum is marked stale until `finish_write`.
+            if self.config.integrity {
+                allocation.rehash_pending = true;
+            }
+            allocation.data[range].copy_from_slice(bytes);
+            Ok(())
+        })
+    }
+
+    fn finish_write(&self, id: Uuid) -> Result<(), AppError> {
+        self.with_allocation(id, |allocation| {
+            allocation.record_access();
+            if self.config.integrity {
+                allocation.update_checksum();
+            }
+            Ok(())
+        })
+    }
+
+    fn size_of(&self, id: Uuid) -> Result<usize, AppError> {
+        self.with_allocation(id, |allocation| Ok(allocation.size_bytes))
+    }
+
+    fn allocations(&self) -> Vec<AllocationInfo> {
+        let allocations = lock(&self.allocations);
+        let now = SystemTime::now();
+        allocations.values().map(|alloc| alloc.info(now)).collect()
+    }
+
+    fn flush(&self) -> Vec<AllocationInfo> {
+        let mut allocations = lock(&self.allocations);
+        let now = SystemTime::now();
+        let removed = allocations.drain().map(|(_, alloc)| alloc.info(now)).collect();
+        lock(&self.dedup_index).clear();
+        removed
+    }
+
+    fn export(&self, include_data: bool) -> Vec<AllocationRecord> {
+        let allocations = lock(&self.allocations);
+        allocations.values().map(|alloc| alloc.record(include_data)).collect()
+    }
+
+    fn import(&self, records: Vec<AllocationRecord>, replace: bool) -> Result<Vec<AllocationInfo>, AppError> {
+        validate_records(&records)?;
+
+        // Build every buffer before touching the map, so a failed mapping
+        // leaves the instance as it was.
+        let mut imported = Vec::with_capacity(records.len());
+        for record in records {
+            let mut data = self.new_buffer(record.size_bytes)?;
+            if let Some(bytes) = &record.data {
+                data.copy_from_slice(bytes);
+            }
+            let mut allocation = MemoryAllocation {
+                id: record.id,
+                size_bytes: record.size_bytes,
+                data,
+                created_at: record.created_at.into(),
+                access_count: record.access_count,
+                last_accessed_at: record.last_accessed_at.map(SystemTime::from),
+                ref_count: record.ref_count,
+                content_hash: None,
+                element_size: record.element_size,
+                element_count: record.element_count,
+                checksum: None,
+            rehash_pending: false,
+            };
+            if self.config.integrity {
+                allocation.update_checksum();
+            }
+            imported.push(allocation);
+        }
+
+        let mut allocations = lock(&self.allocations);
+        if !replace && !allocations.is_empty() {
+            return Err(AppError(
+                StatusCode::CONFLICT,
+                "Instance already has allocations; pass force=true to replace them".to_string(),
+            ));
+        }
+        let now = SystemTime::now();
+        let replaced = allocations.drain().map(|(_, alloc)| alloc.info(now)).collect();
+
+        let mut index = lock(&self.dedup_index);
+        index.clear();
+        for mut allocation in imported {
+            if self.config.dedup {
+                let hash = content_hash(&allocation.data);
+                if let Entry::Vacant(entry) = index.entry(hash) {
+                    entry.insert(allocation.id);
+                    allocation.content_hash = Some(hash);
+                }
+            }
+            allocations.insert(allocation.id, allocation);
+        }
+        Ok(replaced)
+    }
+
+    fn get_active_allocations(&self) -> usize {
+        lock(&self.allocations).len()
+    }
+
+    fn get_total_allocated(&self) -> usize {
+        lock(&self.allocations).values().map(|alloc| alloc.size_bytes).sum()
+    }
+
+    fn try_snapshot(&self) -> Option<(usize, usize)> {
+        let allocations = match self.allocations.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        let total_bytes = allocations.values().map(|alloc| alloc.size_bytes).sum();
+        Some((allocations.len(), total_bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocator(config: MemoryConfig) -> BufferAllocator {
+        BufferAllocator::new(config)
+    }
+
+    impl BufferAllocator {
+        /// Flips a byte behind the allocator's back, as bad memory would.
+        pub(crate) fn corrupt(&self, id: Uuid, offset: usize) {
+            lock(&self.allocations).get_mut(&id).unwrap().data[offset] ^= 0xff;
+        }
+    }
+
+    #[test]
+    fn mmap_anon_round_trip() {
+        let allocator = allocator(MemoryConfig { backing: Backing::MmapAnon, ..MemoryConfig::default() });
+        let info = allocator.allocate(&AllocateRequest::with_size(4096)).unwrap();
+        assert!(matches!(lock(&allocator.allocations)[&info.id].data, AllocationData::MmapAnon(_)));
+
+        assert_eq!(allocator.read(info.id, 0, 4).unwrap(), vec![0; 4]);
+        allocator.write(info.id, 4000, b"mapped").unwrap();
+        assert_eq!(allocator.read(info.id, 4000, 6).unwrap(), b"mapped");
+
+        assert_eq!(allocator.deallocate(info.id).unwrap(), Deallocation::Freed { size_bytes: 4096 });
+        assert_eq!(allocator.get_active_allocations(), 0);
+    }
+
+    #[test]
+    fn reads_and_writes_are_tracked_as_accesses() {
+        let allocator = allocator(MemoryConfig::default());
+        let info = allocator.allocate(&AllocateRequest::with_size(8)).unwrap();
+        assert_eq!(info.access_count, 0);
+        assert!(info.last_accessed_at.is_none());
+
+        allocator.read(info.id, 0, 8).unwrap();
+        let after_read = allocator.allocations().pop().unwrap();
+        assert_eq!(after_read.access_count, 1);
+        let read_at = after_read.last_accessed_at.unwrap();
+
+        std::thread::sleep(Duration::from_millis(2));
+        allocator.write(info.id, 0, b"x").unwrap();
+        let after_write = allocator.allocations().pop().unwrap();
+        assert_eq!(after_write.access_count, 2);
+        assert!(after_write.last_accessed_at.unwrap() > read_at);
+    }
+
+    #[test]
+    fn identical_uploads_share_one_allocation() {
+        let allocator = allocator(MemoryConfig { dedup: true, ..MemoryConfig::default() });
+        let first = allocator.allocate_with_data(b"same bytes").unwrap();
+        let second = allocator.allocate_with_data(b"same bytes").unwrap();
+        assert_eq!(second.id, first.id);
+        assert_eq!(second.ref_count, 2);
+        assert_eq!(allocator.get_active_allocations(), 1);
+
+        assert_eq!(allocator.deallocate(first.id).unwrap(), Deallocation::Released { ref_count: 1 });
+        assert_eq!(allocator.deallocate(first.id).unwrap(), Deallocation::Freed { size_bytes: 10 });
+        assert_eq!(allocator.get_active_allocations(), 0);
+        assert!(lock(&allocator.dedup_index).is_empty());
+    }
+
+    #[test]
+    fn write_removes_allocation_from_dedup_index() {
+        let allocator = allocator(MemoryConfig { dedup: true, ..MemoryConfig::default() });
+        let original = allocator.allocate_with_data(b"content").unwrap();
+        allocator.write(original.id, 0, b"C").unwrap();
+        assert!(lock(&allocator.dedup_index).is_empty());
+
+        let fresh = allocator.allocate_with_data(b"content").unwrap();
+        assert_ne!(fresh.id, original.id);
+        assert_eq!(fresh.ref_count, 1);
+
+        // Streamed writes must drop the entry too.
+        allocator.write_chunk(fresh.id, 0, b"C").unwrap();
+        allocator.finish_write(fresh.id).unwrap();
+        assert!(lock(&allocator.dedup_index).is_empty());
+    }
+
+    #[test]
+    fn dedup_disabled_allocates_every_upload() {
+        let allocator = allocator(MemoryConfig::default());
+        let first = allocator.allocate_with_data(b"same").unwrap();
+        let second = allocator.allocate_with_data(b"same").unwrap();
+        assert_ne!(first.id, second.id);
+    }
+
+    #[test]
+    fn corrupted_data_fails_integrity_check() {
+        let allocator = allocator(MemoryConfig { integrity: true, ..MemoryConfig::default() });
+        let id = allocator.allocate(&AllocateRequest::with_size(16)).unwrap().id;
+        allocator.write(id, 0, b"checked").unwrap();
+        assert_eq!(allocator.read(id, 0, 7).unwrap(), b"checked");
+
+        allocator.corrupt(id, 3);
+        let failures = INTEGRITY_FAILURES.get();
+        let err = allocator.read(id, 0, 7).unwrap_err();
+        assert_eq!(err.0, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(INTEGRITY_FAILURES.get(), failures + 1.0);
+        // The write and the good read count; the failed read does not.
+        assert_eq!(allocator.allocations()[0].access_count, 2);
+    }
+
+    #[test]
+    fn read_during_streamed_write_rehashes_instead_of_skipping_the_check() {
+        let allocator = allocator(MemoryConfig { integrity: true, ..MemoryConfig::default() });
+        let id = allocator.allocate(&AllocateRequest::with_size(8)).unwrap().id;
+        allocator.write_chunk(id, 0, b"half").unwrap();
+        assert_eq!(allocator.read(id, 0, 4).unwrap(), b"half");
+
+        allocator.corrupt(id, 1);
+        assert_eq!(allocator.read(id, 0, 4).unwrap_err().0, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    fn export_import_config() -> MemoryConfig {
+        MemoryConfig { integrity: true, ..MemoryConfig::default() }
+    }
+
+    #[test]
+    fn export_import_round_trip_keeps_ids_and_contents() {
+        let source = allocator(export_import_config());
+        let hello = source.allocate_with_data(b"hello").unwrap();
+        let array = source.allocate(&AllocateRequest { size_bytes: None, element_size: Some(4), element_count: Some(2) }).unwrap();
+        source.write(array.id, 0, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+
+        // Round-trip through JSON, as /admin/export and /admin/import do.
+        let json = serde_json::to_string(&source.export(true)).unwrap();
+        let records: Vec<AllocationRecord> = serde_json::from_str(&json).unwrap();
+
+        let target = allocator(export_import_config());
+        assert!(target.import(records, false).unwrap().is_empty());
+        assert_eq!(target.read(hello.id, 0, 5).unwrap(), b"hello");
+        assert_eq!(target.read(array.id, 0, 8).unwrap(), [1, 2, 3, 4, 5, 6, 7, 8]);
+        let imported = target.allocations().into_iter().find(|info| info.id == array.id).unwrap();
+        assert_eq!((imported.element_size, imported.element_count), (Some(4), Some(2)));
+        assert_eq!(target.get_total_allocated(), source.get_total_allocated());
+    }
+
+    #[test]
+    fn export_without_data_imports_zero_filled() {
+        let source = allocator(MemoryConfig::default());
+        let id = source.allocate_with_data(b"secret").unwrap().id;
+        let records = source.export(false);
+        assert!(records[0].data.is_none());
+
+        let target = allocator(MemoryConfig::default());
+        target.import(records, false).unwrap();
+        assert_eq!(target.read(id, 0, 6).unwrap(), [0; 6]);
+    }
+
+    #[test]
+    fn import_into_non_empty_instance_needs_replace() {
+        let source = allocator(MemoryConfig::default());
+        let imported_id = source.allocate(&AllocateRequest::with_size(4)).unwrap().id;
+        let target = allocator(MemoryConfig::default());
+        let existing_id = target.allocate(&AllocateRequest::with_size(8)).unwrap().id;
+
+        let err = target.import(source.export(true), false).unwrap_err();
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        assert_eq!(target.allocations()[0].id, existing_id);
+
+        let replaced = target.import(source.export(true), true).unwrap();
+        assert_eq!(replaced.iter().map(|info| info.id).collect::<Vec<_>>(), vec![existing_id]);
+        assert_eq!(target.allocations().iter().map(|info| info.id).collect::<Vec<_>>(), vec![imported_id]);
+    }
+
+    #[test]
+    fn import_rejects_inconsistent_records_without_panicking() {
+        let source = allocator(MemoryConfig::default());
+        source.allocate_with_data(b"four").unwrap();
+        let target = allocator(MemoryConfig::default());
+
+        let mut mismatched = source.export(true);
+        mismatched[0].data = Some(b"three".to_vec());
+        assert_eq!(target.import(mismatched, false).unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        let mut duplicated = source.export(true);
+        duplicated.extend(source.export(true));
+        assert_eq!(target.import(duplicated, false).unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(target.get_active_allocations(), 0);
+    }
+}
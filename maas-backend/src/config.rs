@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+/// Environment variable naming the JSON config file to load.
+pub const CONFIG_ENV_VAR: &str = "MAAS_CONFIG";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub memory: MemoryConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub host: IpAddr,
+    pub port: u16,
+    /// Number of recent allocate/deallocate events kept for `/events`.
+    pub event_buffer_size: usize,
+    /// Port for the length-prefixed binary protocol. `None` disables it.
+    pub raw_port: Option<u16>,
+    /// Largest frame payload the binary protocol accepts.
+    pub raw_max_frame_bytes: u32,
+    /// Enables `POST /admin/flush`. Meant for test environments only.
+    pub allow_flush: bool,
+    /// Enables `GET /admin/export` and `POST /admin/import`.
+    pub allow_migration: bool,
+    /// Largest snapshot body `POST /admin/import` accepts.
+    pub max_import_bytes: usize,
+    /// How long shutdown waits for in-flight allocations to finish.
+    pub shutdown_timeout_secs: u64,
+    /// Requests served at once, excluding `/health` and `/metrics`.
+    /// `None` leaves concurrency unbounded.
+    pub max_concurrent_requests: Option<usize>,
+    /// Requests allowed to wait for a slot before new ones get `503`.
+    /// `None` lets the queue grow without limit.
+    pub max_queued_requests: Option<usize>,
+    /// Allocate/deallocate calls at least this slow are logged at `info`,
+    /// faster ones at `trace`. `None` logs every call at `info`.
+    pub slow_op_threshold_ms: Option<u64>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 3000,
+            event_buffer_size: 1024,
+            raw_port: None,
+            raw_max_frame_bytes: 16 * 1024 * 1024,
+            allow_flush: false,
+            allow_migration: false,
+            max_import_bytes: 2 * 1024 * 1024 * 1024,
+            shutdown_timeout_secs: 30,
+            max_concurrent_requests: None,
+            max_queued_requests: None,
+            slow_op_threshold_ms: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+
+    pub fn raw_addr(&self) -> Option<SocketAddr> {
+        self.raw_port.map(|port| SocketAddr::new(self.host, port))
+    }
+
+    pub fn is_slow_op(&self, elapsed: Duration) -> bool {
+        self.slow_op_threshold_ms
+            .is_none_or(|threshold| elapsed >= Duration::from_millis(threshold))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryConfig {
+    pub backing: Backing,
+    /// Largest single allocation accepted, on any transport.
+    pub max_allocation_bytes: usize,
+    /// Allocations older than this are reported as suspected leaks.
+    /// `None` disables the leak detector.
+    pub leak_warn_age_secs: Option<u64>,
+    pub leak_scan_interval_secs: u64,
+    /// Share one allocation between `/allocate/with-data` calls that
+    /// upload identical bytes, reference-counting it until the last free.
+    pub dedup: bool,
+    /// Keep a CRC32 per allocation, refreshed on every write and checked
+    /// on every read, to catch silent corruption.
+    pub integrity: bool,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            backing: Backing::default(),
+            max_allocation_bytes: 1024 * 1024 * 1024,
+            leak_warn_age_secs: None,
+            leak_scan_interval_secs: 60,
+            dedup: false,
+            integrity: false,
+        }
+    }
+}
+
+/// Where allocation buffers live.
+///
+/// `Heap` uses a zeroed `Vec<u8>`. `MmapAnon` uses an anonymous private
+/// mapping instead, so large allocations are only backed by physical pages
+/// once touched and are returned to the OS as soon as they are freed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backing {
+    #[default]
+    Heap,
+    MmapAnon,
+}
+
+impl Config {
+    /// Loads the config from the file named by `MAAS_CONFIG`, or falls back
+    /// to the defaults when the variable is unset. Missing fields take their
+    /// default values.
+    pub fn load() -> Self {
+        let Ok(path) = std::env::var(CONFIG_ENV_VAR) else {
+            return Config::default();
+        };
+        let contents = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Failed to read config file {}: {}", path, e));
+        let config: Config = serde_json::from_str(&contents)
+            .unwrap_or_else(|e| panic!("Failed to parse config file {}: {}", path, e));
+        if let Err(e) = config.validate() {
+            panic!("Invalid config file {}: {}", path, e);
+        }
+        config
+    }
+
+    /// Rejects settings that parse but cannot work.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.server.max_concurrent_requests == Some(0) {
+            return Err("server.max_concurrent_requests must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_concurrency_limit_is_rejected() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+        config.server.max_concurrent_requests = Some(0);
+        assert!(config.validate().is_err());
+        config.server.max_concurrent_requests = Some(1);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn slow_op_threshold_is_inclusive_and_optional() {
+        let mut server = ServerConfig::default();
+        assert!(server.is_slow_op(Duration::ZERO));
+
+        server.slow_op_threshold_ms = Some(10);
+        assert!(!server.is_slow_op(Duration::from_millis(9)));
+        assert!(server.is_slow_op(Duration::from_millis(10)));
+        assert!(server.is_slow_op(Duration::from_secs(1)));
+    }
+}
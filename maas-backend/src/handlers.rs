@@ -1,24 +1,43 @@
 use axum::{
-    extract::{Path, State},
-    response::Json,
-    http::StatusCode,
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    response::{IntoResponse, Json, Response},
+    http::{header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE}, HeaderMap, StatusCode},
 };
+use futures_util::StreamExt;
 use uuid::Uuid;
-use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
+use std::time::{Instant, SystemTime};
+use chrono::Utc;
 use crate::{
-    models::{AllocateRequest, AllocationInfo, AppError, MemoryStats},
-    state::{AppState, MemoryAllocation},
+    allocator::{checked_range, Deallocation},
+    openmetrics,
+    models::{AllocateRequest, AllocationEvent, AllocationInfo, AppError, EventOp, EventsQuery, ExportQuery, FlushSummary, ImportQuery, ImportSummary, MemoryStats, SearchQuery, Snapshot, WriteQuery, WriteResult},
+    state::AppState,
 };
-use prometheus::{Encoder, TextEncoder, register_counter, register_histogram, register_gauge};
+use tracing::{info, trace};
+use prometheus::{Encoder, TextEncoder, register_counter, register_gauge};
 
 // Metrics
 lazy_static::lazy_static! {
-    static ref REQUEST_COUNTER: prometheus::Counter = register_counter!("request_count", "Total number of requests").unwrap();
+    pub(crate) static ref REQUEST_COUNTER: prometheus::Counter = register_counter!("request_count", "Total number of requests").unwrap();
     static ref ALLOCATION_GAUGE: prometheus::Gauge = register_gauge!("active_allocations", "Number of active allocations").unwrap();
     static ref ALLOCATION_SIZE_GAUGE: prometheus::Gauge = register_gauge!("allocation_size_bytes", "Total size of allocated memory in bytes").unwrap();
 }
 
+/// Logs a finished allocate/deallocate at `info` when it was slower than
+/// `server.slow_op_threshold_ms`, and at `trace` otherwise.
+macro_rules! log_op {
+    ($state:expr, $started:expr, $($arg:tt)+) => {{
+        let elapsed = $started.elapsed();
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+        if $state.config.server.is_slow_op(elapsed) {
+            info!(elapsed_ms, $($arg)+);
+        } else {
+            trace!(elapsed_ms, $($arg)+);
+        }
+    }};
+}
+
 // Since we didn't add lazy_static to Cargo.toml, we should add it or use std::sync::OnceLock (if rust 1.70+) or just initialize in main and pass via state?
 // But macros like register_counter! rely on global state.
 // Wait, I forgot to add `lazy_static` to Cargo.toml.
@@ -37,12 +56,25 @@ pub async fn health_check() -> Json<serde_json::Value> {
     }))
 }
 
-pub async fn metrics_handler() -> String {
-    let encoder = TextEncoder::new();
+/// Serves the legacy Prometheus text format unless the scraper asks for
+/// OpenMetrics via `Accept: application/openmetrics-text`.
+pub async fn metrics_handler(headers: HeaderMap) -> Response {
     let metric_families = prometheus::gather();
+
+    let wants_openmetrics = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(openmetrics::is_accepted);
+    if wants_openmetrics {
+        let body = openmetrics::encode(&metric_families);
+        return ([(CONTENT_TYPE, openmetrics::OPENMETRICS_CONTENT_TYPE)], body).into_response();
+    }
+
+    let encoder = TextEncoder::new();
     let mut buffer = vec![];
     encoder.encode(&metric_families, &mut buffer).unwrap();
-    String::from_utf8(buffer).unwrap()
+    String::from_utf8(buffer).unwrap().into_response()
 }
 
 pub async fn stats_handler(
@@ -51,39 +83,32 @@ pub async fn stats_handler(
     Json(state.get_stats())
 }
 
+pub async fn search_handler(
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<AllocationInfo>>, AppError> {
+    query.validate()?;
+    Ok(Json(state.find_allocations(|info| query.matches(info))))
+}
+
+pub async fn events_handler(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> Result<Json<Vec<AllocationEvent>>, AppError> {
+    if let (Some(since), Some(until)) = (query.since, query.until) {
+        if since > until {
+            return Err(AppError(StatusCode::BAD_REQUEST, "`since` must not be after `until`".to_string()));
+        }
+    }
+    Ok(Json(state.events_between(query.since, query.until)))
+}
+
 pub async fn allocate_handler(
     State(state): State<AppState>,
     Json(payload): Json<AllocateRequest>,
 ) -> Result<Json<AllocationInfo>, AppError> {
     REQUEST_COUNTER.inc();
-    
-    // Simulate allocation
-    let data = vec![0u8; payload.size_bytes];
-    let id = Uuid::new_v4();
-    let now = SystemTime::now();
-    
-    let allocation = MemoryAllocation {
-        id,
-        size_bytes: payload.size_bytes,
-        data: Arc::new(data),
-        created_at: now,
-    };
-
-    let info = AllocationInfo {
-        id,
-        size_bytes: allocation.size_bytes,
-        size_mb: allocation.size_bytes as f64 / 1_048_576.0,
-        age_seconds: 0,
-    };
-
-    {
-        let mut allocations = state.allocations.lock().unwrap();
-        allocations.insert(id, allocation);
-        ALLOCATION_GAUGE.set(allocations.len() as f64);
-        ALLOCATION_SIZE_GAUGE.add(payload.size_bytes as f64);
-    }
-    
-    Ok(Json(info))
+    allocate(&state, payload).map(Json)
 }
 
 pub async fn deallocate_handler(
@@ -91,13 +116,422 @@ pub async fn deallocate_handler(
     Path(id): Path<Uuid>,
 ) -> Result<StatusCode, AppError> {
     REQUEST_COUNTER.inc();
+    deallocate(&state, id).map(|()| StatusCode::OK)
+}
+
+pub async fn allocate_with_data_handler(
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Json<AllocationInfo>, AppError> {
+    REQUEST_COUNTER.inc();
+    allocate_with_data(&state, &body).map(Json)
+}
+
+/// Frees every allocation at once. Disabled unless `server.allow_flush` is set.
+pub async fn flush_handler(
+    State(state): State<AppState>,
+) -> Result<Json<FlushSummary>, AppError> {
+    REQUEST_COUNTER.inc();
+    if !state.config.server.allow_flush {
+        return Err(AppError(StatusCode::FORBIDDEN, "Flush is disabled".to_string()));
+    }
+
+    let removed = state.allocator.flush();
+    ALLOCATION_GAUGE.set(state.allocator.get_active_allocations() as f64);
+    ALLOCATION_SIZE_GAUGE.set(state.allocator.get_total_allocated() as f64);
+
+    let mut bytes_reclaimed = 0;
+    for alloc in &removed {
+        bytes_reclaimed += alloc.size_bytes;
+        state.record_event(EventOp::Deallocate, alloc.id, alloc.size_bytes);
+    }
+    info!(freed_allocations = removed.len(), bytes_reclaimed, "flushed all allocations");
+
+    Ok(Json(FlushSummary {
+        freed_allocations: removed.len(),
+        bytes_reclaimed,
+    }))
+}
+
+/// Dumps every allocation for migration to another instance. Contents are
+/// only included with `?include_data=true`. Disabled unless
+/// `server.allow_migration` is set.
+pub async fn export_handler(
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Json<Snapshot>, AppError> {
+    REQUEST_COUNTER.inc();
+    if !state.config.server.allow_migration {
+        return Err(AppError(StatusCode::FORBIDDEN, "Migration is disabled".to_string()));
+    }
+
+    let allocations = state.allocator.export(query.include_data);
+    info!(allocations = allocations.len(), include_data = query.include_data, "exported snapshot");
+    Ok(Json(Snapshot {
+        exported_at: Utc::now(),
+        config: (*state.config).clone(),
+        total_allocated_bytes: allocations.iter().map(|record| record.size_bytes).sum(),
+        active_allocations: allocations.len(),
+        allocations,
+    }))
+}
+
+/// Loads a snapshot from `/admin/export`, keeping the original allocation
+/// ids. Refuses with `409` if this instance already has allocations, unless
+/// `?force=true` is given to replace them. Disabled unless
+/// `server.allow_migration` is set.
+pub async fn import_handler(
+    State(state): State<AppState>,
+    Query(query): Query<ImportQuery>,
+    Json(snapshot): Json<Snapshot>,
+) -> Result<Json<ImportSummary>, AppError> {
+    REQUEST_COUNTER.inc();
+    if !state.config.server.allow_migration {
+        return Err(AppError(StatusCode::FORBIDDEN, "Migration is disabled".to_string()));
+    }
+
+    let _in_flight = state.begin_in_flight();
+    let imported: Vec<(Uuid, usize)> = snapshot
+        .allocations
+        .iter()
+        .map(|record| (record.id, record.size_bytes))
+        .collect();
+    let replaced = state.allocator.import(snapshot.allocations, query.force)?;
+    ALLOCATION_GAUGE.set(state.allocator.get_active_allocations() as f64);
+    ALLOCATION_SIZE_GAUGE.set(state.allocator.get_total_allocated() as f64);
+
+    for alloc in &replaced {
+        state.record_event(EventOp::Deallocate, alloc.id, alloc.size_bytes);
+    }
+    let mut imported_bytes = 0;
+    for &(id, size_bytes) in &imported {
+        imported_bytes += size_bytes;
+        state.record_event(EventOp::Allocate, id, size_bytes);
+    }
+    info!(
+        imported_allocations = imported.len(),
+        imported_bytes,
+        replaced_allocations = replaced.len(),
+        exported_at = %snapshot.exported_at,
+        "imported snapshot"
+    );
+
+    Ok(Json(ImportSummary {
+        imported_allocations: imported.len(),
+        imported_bytes,
+        replaced_allocations: replaced.len(),
+    }))
+}
+
+/// Streams the request body into an allocation starting at `?offset=`.
+///
+/// Each chunk is copied into the allocation as it arrives, so large bodies
+/// are never buffered in full. A `Content-Length` that cannot fit is
+/// rejected with `413` before anything is read; a body that turns out
+/// longer than announced is rejected with `413` as soon as it overruns,
+/// leaving the chunks already copied in place.
+pub async fn write_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<WriteQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<WriteResult>, AppError> {
+    REQUEST_COUNTER.inc();
+
+    let start = query.offset.unwrap_or(0);
+    let size_bytes = state.allocator.size_of(id)?;
+    checked_range(start, 0, size_bytes)?;
+
+    let too_large = || AppError(StatusCode::PAYLOAD_TOO_LARGE, "Body exceeds allocation size".to_string());
+    let content_length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if let Some(len) = content_length {
+        checked_range(start, len, size_bytes).map_err(|_| too_large())?;
+    }
+
+    let finish = FinishWrite { state: &state, id, armed: true };
+    let mut offset = start;
+    let streamed = async {
+        let mut chunks = body.into_data_stream();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk
+                .map_err(|e| AppError(StatusCode::BAD_REQUEST, format!("Failed to read body: {}", e)))?;
+            checked_range(offset, chunk.len(), size_bytes).map_err(|_| too_large())?;
+            state.allocator.write_chunk(id, offset, &chunk)?;
+            offset += chunk.len();
+        }
+        Ok(())
+    }
+    .await;
+
+    // Runs even if the stream failed part-way, since earlier chunks landed.
+    finish.finish()?;
+    streamed?;
 
-    let mut allocations = state.allocations.lock().unwrap();
-    if let Some(removed) = allocations.remove(&id) {
-        ALLOCATION_GAUGE.set(allocations.len() as f64);
-        ALLOCATION_SIZE_GAUGE.sub(removed.size_bytes as f64);
-        Ok(StatusCode::OK)
+    Ok(Json(WriteResult { bytes_written: offset - start }))
+}
+
+/// Calls `finish_write` if `write_handler` is dropped mid-stream, e.g.
+/// because the client disconnected, so the chunks that landed are still
+/// counted and checksummed.
+struct FinishWrite<'a> {
+    state: &'a AppState,
+    id: Uuid,
+    armed: bool,
+}
+
+impl FinishWrite<'_> {
+    fn finish(mut self) -> Result<(), AppError> {
+        self.armed = false;
+        self.state.allocator.finish_write(self.id)
+    }
+}
+
+impl Drop for FinishWrite<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            let _ = self.state.allocator.finish_write(self.id);
+        }
+    }
+}
+
+/// Allocation logic shared by every transport (HTTP, JSON-RPC).
+pub fn allocate(state: &AppState, payload: AllocateRequest) -> Result<AllocationInfo, AppError> {
+    let started = Instant::now();
+    let _in_flight = state.begin_in_flight();
+    let info = state.allocator.allocate(&payload)?;
+    record_allocation(state, &info, started);
+    Ok(info)
+}
+
+/// Allocates a buffer holding `bytes`. With `memory.dedup` enabled, an
+/// existing allocation with identical contents is shared instead and its
+/// reference count bumped.
+pub fn allocate_with_data(state: &AppState, bytes: &[u8]) -> Result<AllocationInfo, AppError> {
+    let started = Instant::now();
+    let _in_flight = state.begin_in_flight();
+    let info = state.allocator.allocate_with_data(bytes)?;
+    if info.ref_count > 1 {
+        log_op!(state, started, id = %info.id, ref_count = info.ref_count, "deduplicated allocation");
     } else {
-        Err(AppError(StatusCode::NOT_FOUND, "Allocation not found".to_string()))
+        record_allocation(state, &info, started);
+    }
+    Ok(info)
+}
+
+fn record_allocation(state: &AppState, info: &AllocationInfo, started: Instant) {
+    ALLOCATION_GAUGE.set(state.allocator.get_active_allocations() as f64);
+    ALLOCATION_SIZE_GAUGE.add(info.size_bytes as f64);
+    state.record_event(EventOp::Allocate, info.id, info.size_bytes);
+    log_op!(state, started, id = %info.id, size_bytes = info.size_bytes, "allocated");
+}
+
+/// Releases one reference to an allocation, freeing it when none remain.
+pub fn deallocate(state: &AppState, id: Uuid) -> Result<(), AppError> {
+    let started = Instant::now();
+    match state.allocator.deallocate(id)? {
+        Deallocation::Released { ref_count } => {
+            log_op!(state, started, %id, ref_count, "released shared allocation");
+        }
+        Deallocation::Freed { size_bytes } => {
+            ALLOCATION_GAUGE.set(state.allocator.get_active_allocations() as f64);
+            ALLOCATION_SIZE_GAUGE.sub(size_bytes as f64);
+            state.record_event(EventOp::Deallocate, id, size_bytes);
+            log_op!(state, started, %id, size_bytes, "deallocated");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::allocator::BufferAllocator;
+    use crate::test_util::MockAllocator;
+    use crate::config::Config;
+
+    fn state_with(configure: impl FnOnce(&mut Config)) -> AppState {
+        let mut config = Config::default();
+        configure(&mut config);
+        AppState::new(config)
+    }
+
+    #[tokio::test]
+    async fn flush_frees_everything_and_allocation_still_works() {
+        let state = state_with(|config| config.server.allow_flush = true);
+        for size in 1..=50 {
+            allocate(&state, AllocateRequest::with_size(size)).unwrap();
+        }
+
+        let Json(summary) = flush_handler(State(state.clone())).await.unwrap();
+        assert_eq!(summary.freed_allocations, 50);
+        assert_eq!(summary.bytes_reclaimed, (1..=50).sum::<usize>());
+        assert_eq!(state.allocator.get_active_allocations(), 0);
+
+        allocate(&state, AllocateRequest::with_size(8)).unwrap();
+        assert_eq!(state.allocator.get_active_allocations(), 1);
+    }
+
+    #[tokio::test]
+    async fn flush_is_forbidden_by_default() {
+        let state = state_with(|_| {});
+        allocate(&state, AllocateRequest::with_size(8)).unwrap();
+        let err = flush_handler(State(state.clone())).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::FORBIDDEN);
+        assert_eq!(state.allocator.get_active_allocations(), 1);
+    }
+
+    fn chunked_body(chunks: &[&'static [u8]]) -> Body {
+        let chunks: Vec<Result<Bytes, std::io::Error>> = chunks.iter().map(|chunk| Ok(Bytes::from_static(chunk))).collect();
+        Body::from_stream(futures_util::stream::iter(chunks))
+    }
+
+    async fn put_data(state: &AppState, id: Uuid, offset: Option<usize>, headers: HeaderMap, body: Body) -> Result<usize, AppError> {
+        let query = WriteQuery { offset };
+        write_handler(State(state.clone()), Path(id), Query(query), headers, body)
+            .await
+            .map(|Json(result)| result.bytes_written)
+    }
+
+    #[tokio::test]
+    async fn dropped_streamed_write_keeps_integrity_checking() {
+        let mut config = Config::default();
+        config.memory.integrity = true;
+        let allocator = Arc::new(BufferAllocator::new(config.memory.clone()));
+        let state = AppState::with_allocator(config, allocator.clone());
+        let id = allocate(&state, AllocateRequest::with_size(8)).unwrap().id;
+
+        // One chunk, then the client goes quiet; axum drops the handler
+        // future when the connection closes, as the timeout does here.
+        let first: Result<Bytes, std::io::Error> = Ok(Bytes::from_static(b"abcd"));
+        let body = Body::from_stream(futures_util::stream::iter([first]).chain(futures_util::stream::pending()));
+        let write = put_data(&state, id, None, HeaderMap::new(), body);
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(20), write).await.is_err());
+
+        allocator.corrupt(id, 2);
+        assert_eq!(state.allocator.read(id, 0, 8).unwrap_err().0, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(state.allocator.allocations()[0].access_count, 1);
+    }
+
+    #[tokio::test]
+    async fn streamed_write_lands_every_chunk() {
+        let state = state_with(|config| config.memory.integrity = true);
+        let id = allocate(&state, AllocateRequest::with_size(12)).unwrap().id;
+
+        let written = put_data(&state, id, Some(1), HeaderMap::new(), chunked_body(&[b"abc", b"defg", b"hij"])).await.unwrap();
+        assert_eq!(written, 10);
+        assert_eq!(state.allocator.read(id, 0, 12).unwrap(), b"\0abcdefghij\0");
+        // One access for the write, one for the read above.
+        assert_eq!(state.allocator.allocations()[0].access_count, 2);
+    }
+
+    #[tokio::test]
+    async fn overrun_mid_stream_is_rejected_after_earlier_chunks_land() {
+        let state = state_with(|_| {});
+        let id = allocate(&state, AllocateRequest::with_size(8)).unwrap().id;
+
+        let err = put_data(&state, id, None, HeaderMap::new(), chunked_body(&[b"abcd", b"efgh", b"ij"])).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(state.allocator.read(id, 0, 8).unwrap(), b"abcdefgh");
+    }
+
+    #[tokio::test]
+    async fn oversized_content_length_is_rejected_up_front() {
+        let state = state_with(|_| {});
+        let id = allocate(&state, AllocateRequest::with_size(8)).unwrap().id;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, "9".parse().unwrap());
+        let err = put_data(&state, id, None, headers, chunked_body(&[b"abcdefghi"])).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(state.allocator.read(id, 0, 8).unwrap(), [0; 8]);
+    }
+
+    async fn scrape(accept: Option<&str>) -> (String, String) {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(ACCEPT, accept.parse().unwrap());
+        }
+        REQUEST_COUNTER.inc();
+        let response = metrics_handler(headers).await;
+        let content_type = response.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (content_type, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn metrics_format_follows_accept_header() {
+        for accept in [
+            "application/openmetrics-text",
+            "application/openmetrics-text; version=1.0.0, text/plain;q=0.5",
+            "text/plain;q=0.5, Application/OpenMetrics-Text;q=0.9",
+        ] {
+            let (content_type, body) = scrape(Some(accept)).await;
+            assert_eq!(content_type, openmetrics::OPENMETRICS_CONTENT_TYPE);
+            assert!(body.ends_with("# EOF\n"));
+            assert!(body.contains("# TYPE request_count counter"), "{}", body);
+        }
+
+        for accept in [
+            None,
+            Some("text/plain"),
+            Some("*/*"),
+            Some("application/openmetrics-text;q=0"),
+            Some("text/plain, application/openmetrics-text; version=1.0.0; q=0.0"),
+        ] {
+            let (content_type, body) = scrape(accept).await;
+            assert!(content_type.starts_with("text/plain"), "{}", content_type);
+            assert!(!body.contains("# EOF"));
+            assert!(body.contains("request_count"), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn handlers_work_against_a_custom_allocator() {
+        let mock = Arc::new(MockAllocator::default());
+        let state = AppState::with_allocator(Config::default(), mock.clone());
+
+        let Json(info) = allocate_handler(State(state.clone()), Json(AllocateRequest::with_size(256))).await.unwrap();
+        assert_eq!(mock.allocations.lock().unwrap()[0].id, info.id);
+
+        let Json(stats) = stats_handler(State(state.clone())).await;
+        assert_eq!(stats.active_allocations, 1);
+        assert_eq!(stats.total_allocated_bytes, 256);
+        assert_eq!(stats.allocations[0].id, info.id);
+
+        assert_eq!(deallocate_handler(State(state.clone()), Path(info.id)).await.unwrap(), StatusCode::OK);
+        assert!(mock.allocations.lock().unwrap().is_empty());
+        let ops: Vec<EventOp> = state.events_between(None, None).iter().map(|event| event.op).collect();
+        assert_eq!(ops, vec![EventOp::Allocate, EventOp::Deallocate]);
+    }
+
+    #[test]
+    fn only_slow_operations_are_logged_at_info() {
+        let mut config = Config::default();
+        config.server.slow_op_threshold_ms = Some(20);
+        let slow = AppState::with_allocator(
+            config.clone(),
+            Arc::new(MockAllocator { delay: std::time::Duration::from_millis(30), ..MockAllocator::default() }),
+        );
+        let fast = AppState::new(config);
+
+        let (logs, guard) = crate::test_util::capture_logs(tracing::Level::INFO);
+        let fast_id = allocate(&fast, AllocateRequest::with_size(8)).unwrap().id;
+        assert!(!logs.contents().contains("allocated"), "{}", logs.contents());
+        let slow_id = allocate(&slow, AllocateRequest::with_size(8)).unwrap().id;
+        let info_output = logs.contents();
+        assert!(info_output.contains(&format!("id={}", slow_id)), "{}", info_output);
+        assert!(info_output.contains("elapsed_ms="), "{}", info_output);
+        drop(guard);
+
+        // Fast operations are still there at trace level.
+        let (logs, _guard) = crate::test_util::capture_logs(tracing::Level::TRACE);
+        deallocate(&fast, fast_id).unwrap();
+        assert!(logs.contents().contains("deallocated"), "{}", logs.contents());
+        assert!(logs.contents().contains(" TRACE "), "{}", logs.contents());
     }
 }
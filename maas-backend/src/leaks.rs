@@ -0,0 +1,68 @@
+use std::time::Duration;
+use prometheus::register_gauge;
+use tracing::warn;
+use crate::state::AppState;
+
+lazy_static::lazy_static! {
+    static ref SUSPECTED_LEAKS_GAUGE: prometheus::Gauge = register_gauge!("maas_suspected_leaks", "Number of allocations older than the leak warning age").unwrap();
+}
+
+/// Periodically logs allocations that have outlived `warn_age`.
+///
+/// Allocations have no TTL or lease in this service, so every long-lived
+/// allocation is a candidate. Nothing is freed here; the detector only
+/// surfaces them through logs and the `maas_suspected_leaks` gauge.
+pub async fn run_leak_detector(state: AppState, warn_age: Duration, scan_interval: Duration) {
+    let mut interval = tokio::time::interval(scan_interval);
+    loop {
+        interval.tick().await;
+        scan_for_leaks(&state, warn_age);
+    }
+}
+
+pub fn scan_for_leaks(state: &AppState, warn_age: Duration) {
+    let suspects = state.allocations_older_than(warn_age);
+    for alloc in &suspects {
+        warn!(
+            id = %alloc.id,
+            size_bytes = alloc.size_bytes,
+            age_seconds = alloc.age_seconds,
+            "suspected leak: allocation exceeded leak warning age"
+        );
+    }
+    SUSPECTED_LEAKS_GAUGE.set(suspects.len() as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+    use crate::config::Config;
+    use crate::handlers::allocate;
+    use crate::models::{AllocateRequest, AllocationRecord};
+
+    #[test]
+    fn old_allocations_are_counted_as_suspected_leaks() {
+        let state = AppState::new(Config::default());
+        let old = AllocationRecord {
+            id: Uuid::new_v4(),
+            size_bytes: 8,
+            created_at: Utc::now() - chrono::Duration::hours(1),
+            access_count: 0,
+            last_accessed_at: None,
+            ref_count: 1,
+            element_size: None,
+            element_count: None,
+            data: None,
+        };
+        state.allocator.import(vec![old], false).unwrap();
+        allocate(&state, AllocateRequest::with_size(8)).unwrap();
+
+        scan_for_leaks(&state, Duration::from_secs(60));
+        assert_eq!(SUSPECTED_LEAKS_GAUGE.get(), 1.0);
+
+        scan_for_leaks(&state, Duration::from_secs(2 * 3600));
+        assert_eq!(SUSPECTED_LEAKS_GAUGE.get(), 0.0);
+    }
+}
@@ -1,33 +1,265 @@
+mod allocator;
+mod config;
 mod handlers;
+mod leaks;
+mod middleware;
 mod models;
+mod openmetrics;
+mod raw;
+mod rpc;
 mod state;
+#[cfg(test)]
+mod test_util;
 
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
 use axum::{
-    routing::{get, post, delete},
+    extract::DefaultBodyLimit,
+    routing::{get, post, put, delete},
     Router,
 };
-use std::net::SocketAddr;
+use crate::config::Config;
+use crate::models::MemoryStats;
 use crate::state::AppState;
-use crate::handlers::{allocate_handler, deallocate_handler, health_check, metrics_handler, stats_handler};
+use crate::handlers::{allocate_handler, allocate_with_data_handler, deallocate_handler, events_handler, export_handler, flush_handler, health_check, import_handler, metrics_handler, search_handler, stats_handler, write_handler};
+use crate::middleware::{limit_concurrency, trace_context, ConcurrencyLimit};
+use crate::rpc::rpc_handler;
 
 #[tokio::main]
 async fn main() {
     // Initialize logging
     tracing_subscriber::fmt::init();
 
-    let state = AppState::new();
+    let config = Config::load();
+    let addr = config.server.addr();
+    let state = AppState::new(config);
+    install_panic_hook(state.clone());
 
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .route("/metrics", get(metrics_handler))
+    if let Some(warn_age) = state.config.memory.leak_warn_age_secs {
+        tokio::spawn(leaks::run_leak_detector(
+            state.clone(),
+            Duration::from_secs(warn_age),
+            Duration::from_secs(state.config.memory.leak_scan_interval_secs.max(1)),
+        ));
+    }
+
+    // Flipped on the shutdown signal to stop listeners axum doesn't manage.
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+
+    let raw_server = match state.config.server.raw_addr() {
+        Some(raw_addr) => {
+            let raw_listener = tokio::net::TcpListener::bind(raw_addr).await.unwrap();
+            println!("Raw protocol listening on {}", raw_addr);
+            Some(tokio::spawn(raw::serve(raw_listener, state.clone(), shutdown_rx)))
+        }
+        None => None,
+    };
+
+    let mut api = Router::new()
         .route("/stats", get(stats_handler))
+        .route("/events", get(events_handler))
+        .route("/allocations/search", get(search_handler))
         .route("/allocate", post(allocate_handler))
+        .route("/allocate/with-data", post(allocate_with_data_handler))
         .route("/allocate/:id", delete(deallocate_handler))
-        .with_state(state);
+        .route("/allocate/:id/data", put(write_handler))
+        .route("/rpc", post(rpc_handler))
+        .route("/admin/flush", post(flush_handler))
+        .route("/admin/export", get(export_handler))
+        // Snapshots with contents are routinely larger than the default limit.
+        .route(
+            "/admin/import",
+            post(import_handler).layer(DefaultBodyLimit::max(state.config.server.max_import_bytes)),
+        );
+
+    // Probes bypass the limit so a saturated server still reports health.
+    if let Some(max_concurrent) = state.config.server.max_concurrent_requests {
+        let limit = ConcurrencyLimit::new(max_concurrent, state.config.server.max_queued_requests);
+        api = api.route_layer(axum::middleware::from_fn_with_state(limit, limit_concurrency));
+    }
+
+    let app = Router::new()
+        .route("/health", get(health_check))
+        .route("/metrics", get(metrics_handler))
+        .merge(api)
+        .layer(axum::middleware::from_fn(trace_context))
+        .with_state(state.clone());
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     println!("Listening on {}", addr);
     
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            let _ = shutdown_tx.send(true);
+        })
+        .await
+        .unwrap();
+    finish_shutdown(&state, raw_server).await;
+}
+
+/// Waits, within `server.shutdown_timeout_secs` overall, for the raw
+/// protocol server to close its connections and for every allocation already
+/// started to be committed, then logs and returns the final snapshot.
+async fn finish_shutdown(state: &AppState, raw_server: Option<JoinHandle<()>>) -> MemoryStats {
+    let timeout = Duration::from_secs(state.config.server.shutdown_timeout_secs);
+    let deadline = tokio::time::Instant::now() + timeout;
+    if let Some(raw_server) = raw_server {
+        if tokio::time::timeout_at(deadline, raw_server).await.is_err() {
+            warn!("shutdown timed out waiting for raw protocol connections");
+        }
+    }
+
+    let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+    if !state.drain_in_flight(remaining).await {
+        warn!(
+            in_flight = state.in_flight.load(Ordering::SeqCst),
+            "shutdown timed out waiting for in-flight allocations"
+        );
+    }
+    let stats = state.get_stats();
+    info!(
+        active_allocations = stats.active_allocations,
+        allocated_bytes = stats.total_allocated_bytes,
+        "shutdown complete"
+    );
+    stats
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.unwrap();
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .unwrap()
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("shutdown signal received, draining");
+}
+
+/// Logs a snapshot of allocator state alongside any panic, then defers to
+/// the default hook. Handler panics only abort their own request; the
+/// poison-recovering locks in `state` keep the service usable afterwards.
+fn install_panic_hook(state: AppState) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        log_panic(&state, panic_info);
+        default_hook(panic_info);
+    }));
+}
+
+fn log_panic(state: &AppState, panic: &dyn std::fmt::Display) {
+    match state.allocator.try_snapshot() {
+        Some((active_allocations, allocated_bytes)) => error!(
+            active_allocations,
+            allocated_bytes,
+            "panic: {}",
+            panic
+        ),
+        None => error!("panic while allocations lock was held: {}", panic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::handlers::allocate;
+    use crate::models::AllocateRequest;
+    use crate::state::lock;
+    use crate::test_util::{capture_logs, MockAllocator};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn shutdown_waits_for_allocation_in_progress() {
+        let mock = MockAllocator { delay: Duration::from_millis(100), ..MockAllocator::default() };
+        let state = AppState::with_allocator(Config::default(), Arc::new(mock));
+        let worker_state = state.clone();
+        let worker = std::thread::spawn(move || allocate(&worker_state, AllocateRequest::with_size(64)));
+        while state.in_flight.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let stats = finish_shutdown(&state, None).await;
+        assert_eq!(stats.active_allocations, 1);
+        assert_eq!(stats.total_allocated_bytes, 64);
+        worker.join().unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_raw_frame_being_read() {
+        let state = AppState::new(Config::default());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let raw_server = tokio::spawn(raw::serve(listener, state.clone(), shutdown_rx));
+
+        // Header plus half of the payload, so the connection is parked in
+        // the payload read when shutdown starts.
+        let mut frame = vec![raw::OP_ALLOCATE];
+        frame.extend_from_slice(&8u32.to_be_bytes());
+        frame.extend_from_slice(&32u64.to_be_bytes());
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client.write_all(&frame[..9]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        shutdown_tx.send(true).unwrap();
+        let shutdown_state = state.clone();
+        let shutdown = tokio::spawn(async move { finish_shutdown(&shutdown_state, Some(raw_server)).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!shutdown.is_finished());
+
+        client.write_all(&frame[9..]).await.unwrap();
+        let mut status = [0u8; 1];
+        client.read_exact(&mut status).await.unwrap();
+        assert_eq!(status[0], raw::STATUS_OK);
+        let stats = shutdown.await.unwrap();
+        assert_eq!(stats.active_allocations, 1);
+        assert_eq!(stats.total_allocated_bytes, 32);
+    }
+
+    #[test]
+    fn panic_is_logged_with_allocator_snapshot() {
+        let (logs, _guard) = capture_logs(tracing::Level::WARN);
+        let state = AppState::new(Config::default());
+        allocate(&state, AllocateRequest::with_size(64)).unwrap();
+
+        log_panic(&state, &"handler blew up");
+        let output = logs.contents();
+        assert!(output.contains("panic: handler blew up"), "{}", output);
+        assert!(output.contains("active_allocations=1 allocated_bytes=64"), "{}", output);
+    }
+
+    #[tokio::test]
+    async fn service_keeps_working_after_a_task_panics() {
+        let state = AppState::new(Config::default());
+        allocate(&state, AllocateRequest::with_size(64)).unwrap();
+
+        let task_state = state.clone();
+        let outcome = tokio::spawn(async move {
+            let _events = lock(&task_state.events);
+            panic!("handler blew up");
+        })
+        .await;
+        assert!(outcome.unwrap_err().is_panic());
+
+        // The poisoned events lock is recovered and later requests succeed.
+        allocate(&state, AllocateRequest::with_size(8)).unwrap();
+        assert_eq!(state.get_stats().active_allocations, 2);
+        assert_eq!(state.events_between(None, None).len(), 2);
+    }
 }
@@ -0,0 +1,247 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+use uuid::Uuid;
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// The parts of a W3C `traceparent` header we propagate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: String,
+    pub parent_id: String,
+}
+
+impl TraceParent {
+    /// Parses `{version}-{trace-id}-{parent-id}-{flags}` as specified by the
+    /// W3C Trace Context spec. Returns `None` for anything malformed,
+    /// including the all-zero ids the spec marks as invalid.
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_id = parts.next()?;
+        let flags = parts.next()?;
+
+        // Future versions may append fields; version 00 must have exactly four.
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        if !is_lower_hex(version, 2) || version == "ff" || !is_lower_hex(flags, 2) {
+            return None;
+        }
+        if !is_lower_hex(trace_id, 32) || trace_id.bytes().all(|b| b == b'0') {
+            return None;
+        }
+        if !is_lower_hex(parent_id, 16) || parent_id.bytes().all(|b| b == b'0') {
+            return None;
+        }
+
+        Some(Self {
+            trace_id: trace_id.to_string(),
+            parent_id: parent_id.to_string(),
+        })
+    }
+}
+
+fn is_lower_hex(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Runs each request inside a span carrying the caller's trace id, so any
+/// log emitted while handling it can be correlated with upstream traces.
+/// Requests without a valid `traceparent` start a new trace.
+pub async fn trace_context(req: Request, next: Next) -> Response {
+    let parent = req
+        .headers()
+        .get(TRACEPARENT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(TraceParent::parse);
+
+    let span = match parent {
+        Some(parent) => tracing::info_span!(
+            "request",
+            method = %req.method(),
+            path = %req.uri().path(),
+            trace_id = %parent.trace_id,
+            parent_id = %parent.parent_id,
+        ),
+        None => tracing::info_span!(
+            "request",
+            method = %req.method(),
+            path = %req.uri().path(),
+            trace_id = %Uuid::new_v4().simple(),
+        ),
+    };
+
+    next.run(req).instrument(span).await
+}
+
+/// Caps how many requests run at once so bursts queue up instead of all
+/// contending for the allocations lock.
+#[derive(Clone)]
+pub struct ConcurrencyLimit {
+    permits: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+    max_queued: Option<usize>,
+}
+
+impl ConcurrencyLimit {
+    /// `max_queued` bounds how many requests may wait for a slot; `None`
+    /// lets the queue grow without limit.
+    pub fn new(max_concurrent: usize, max_queued: Option<usize>) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            queued: Arc::new(AtomicUsize::new(0)),
+            max_queued,
+        }
+    }
+}
+
+/// Releases a queue slot even if the waiting request is cancelled.
+struct QueueSlot(Arc<AtomicUsize>);
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Runs the request once a concurrency slot is free. Requests that would
+/// overflow a bounded queue are turned away with `503`.
+pub async fn limit_concurrency(
+    State(limit): State<ConcurrencyLimit>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let _permit = match limit.permits.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            let position = limit.queued.fetch_add(1, Ordering::SeqCst);
+            let _slot = QueueSlot(limit.queued.clone());
+            if limit.max_queued.is_some_and(|max| position >= max) {
+                return (StatusCode::SERVICE_UNAVAILABLE, "Too many concurrent requests").into_response();
+            }
+            // The semaphore is never closed, so acquiring cannot fail.
+            limit.permits.clone().acquire_owned().await.unwrap()
+        }
+    };
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+    use tracing::Level;
+    use crate::test_util::capture_logs;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    #[test]
+    fn parses_valid_traceparent() {
+        let parsed = TraceParent::parse(&format!("00-{}-{}-01", TRACE_ID, PARENT_ID)).unwrap();
+        assert_eq!(parsed.trace_id, TRACE_ID);
+        assert_eq!(parsed.parent_id, PARENT_ID);
+    }
+
+    #[test]
+    fn rejects_all_zero_ids() {
+        assert_eq!(TraceParent::parse(&format!("00-{}-{}-01", "0".repeat(32), PARENT_ID)), None);
+        assert_eq!(TraceParent::parse(&format!("00-{}-{}-01", TRACE_ID, "0".repeat(16))), None);
+    }
+
+    #[test]
+    fn rejects_uppercase_hex() {
+        let header = format!("00-{}-{}-01", TRACE_ID.to_uppercase(), PARENT_ID);
+        assert_eq!(TraceParent::parse(&header), None);
+    }
+
+    #[test]
+    fn rejects_version_ff() {
+        assert_eq!(TraceParent::parse(&format!("ff-{}-{}-01", TRACE_ID, PARENT_ID)), None);
+    }
+
+    #[tokio::test]
+    async fn request_span_carries_incoming_trace_id() {
+        let (logs, _guard) = capture_logs(Level::INFO);
+        let app = Router::new()
+            .route("/", get(|| async { tracing::info!("handled") }))
+            .layer(axum::middleware::from_fn(trace_context));
+
+        let request = Request::builder()
+            .uri("/")
+            .header(TRACEPARENT_HEADER, format!("00-{}-{}-01", TRACE_ID, PARENT_ID))
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap();
+
+        let line = logs.contents();
+        assert!(line.contains("handled"), "{}", line);
+        assert!(line.contains(&format!("trace_id={}", TRACE_ID)), "{}", line);
+        assert!(line.contains(&format!("parent_id={}", PARENT_ID)), "{}", line);
+    }
+
+    /// Router shaped like `main`'s: `/slow` is limited, `/health` is not.
+    /// `/slow` holds its slot until the test adds a permit to `gate`.
+    fn limited_app(limit: ConcurrencyLimit, gate: Arc<Semaphore>) -> Router {
+        let api = Router::new()
+            .route(
+                "/slow",
+                get(move || {
+                    let gate = gate.clone();
+                    async move { gate.acquire().await.unwrap().forget() }
+                }),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(limit, limit_concurrency));
+        Router::new().route("/health", get(|| async { "ok" })).merge(api)
+    }
+
+    async fn get_status(app: &Router, path: &str) -> StatusCode {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        while !condition() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn excess_requests_queue_then_get_503() {
+        let limit = ConcurrencyLimit::new(1, Some(1));
+        let gate = Arc::new(Semaphore::new(0));
+        let app = limited_app(limit.clone(), gate.clone());
+
+        let running = tokio::spawn({
+            let app = app.clone();
+            async move { get_status(&app, "/slow").await }
+        });
+        wait_until(|| limit.permits.available_permits() == 0).await;
+
+        let queued = tokio::spawn({
+            let app = app.clone();
+            async move { get_status(&app, "/slow").await }
+        });
+        wait_until(|| limit.queued.load(Ordering::SeqCst) == 1).await;
+
+        assert_eq!(get_status(&app, "/slow").await, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(get_status(&app, "/health").await, StatusCode::OK);
+
+        gate.add_permits(2);
+        assert_eq!(running.await.unwrap(), StatusCode::OK);
+        assert_eq!(queued.await.unwrap(), StatusCode::OK);
+        assert_eq!(limit.queued.load(Ordering::SeqCst), 0);
+        assert_eq!(limit.permits.available_permits(), 1);
+    }
+}
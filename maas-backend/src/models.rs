@@ -1,15 +1,43 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use chrono::{DateTime, Utc};
 use axum::{
     response::{IntoResponse, Response},
-    Json,
     http::StatusCode,
 };
-use std::time::SystemTime;
+use crate::config::Config;
 
-#[derive(Debug, Deserialize)]
+/// Size is given either directly as `size_bytes` or as an array shape
+/// (`element_size` * `element_count`), never both.
+#[derive(Debug, Default, Deserialize)]
 pub struct AllocateRequest {
-    pub size_bytes: usize,
+    pub size_bytes: Option<usize>,
+    pub element_size: Option<usize>,
+    pub element_count: Option<usize>,
+}
+
+impl AllocateRequest {
+    pub fn with_size(size_bytes: usize) -> Self {
+        Self {
+            size_bytes: Some(size_bytes),
+            ..Self::default()
+        }
+    }
+
+    /// The number of bytes to allocate, validating the request's shape.
+    pub fn resolve_size(&self) -> Result<usize, AppError> {
+        let bad_request = |message: &str| Err(AppError(StatusCode::BAD_REQUEST, message.to_string()));
+        match (self.size_bytes, self.element_size, self.element_count) {
+            (Some(size_bytes), None, None) => Ok(size_bytes),
+            (None, Some(element_size), Some(element_count)) => match element_size.checked_mul(element_count) {
+                Some(size_bytes) => Ok(size_bytes),
+                None => bad_request("element_size * element_count overflows"),
+            },
+            (Some(_), _, _) => bad_request("size_bytes cannot be combined with element_size/element_count"),
+            (None, None, None) => bad_request("Either size_bytes or element_size and element_count is required"),
+            (None, _, _) => bad_request("element_size and element_count must be given together"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -18,6 +46,11 @@ pub struct AllocationInfo {
     pub size_bytes: usize,
     pub size_mb: f64,
     pub age_seconds: u64,
+    pub access_count: u64,
+    pub last_accessed_at: Option<DateTime<Utc>>,
+    pub ref_count: usize,
+    pub element_size: Option<usize>,
+    pub element_count: Option<usize>,
 }
 
 #[derive(Debug, Serialize)]
@@ -28,6 +61,186 @@ pub struct MemoryStats {
     pub allocations: Vec<AllocationInfo>,
 }
 
+/// Bounds for `/allocations/search`; sizes in bytes, ages in seconds.
+/// Every bound is inclusive and an omitted bound is unbounded.
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub min_size: Option<usize>,
+    pub max_size: Option<usize>,
+    pub min_age: Option<u64>,
+    pub max_age: Option<u64>,
+}
+
+impl SearchQuery {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if let (Some(min), Some(max)) = (self.min_size, self.max_size) {
+            if min > max {
+                return Err(AppError(StatusCode::BAD_REQUEST, "min_size must not exceed max_size".to_string()));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_age, self.max_age) {
+            if min > max {
+                return Err(AppError(StatusCode::BAD_REQUEST, "min_age must not exceed max_age".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, info: &AllocationInfo) -> bool {
+        self.min_size.is_none_or(|min| info.size_bytes >= min)
+            && self.max_size.is_none_or(|max| info.size_bytes <= max)
+            && self.min_age.is_none_or(|min| info.age_seconds >= min)
+            && self.max_age.is_none_or(|max| info.age_seconds <= max)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WriteQuery {
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WriteResult {
+    pub bytes_written: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FlushSummary {
+    pub freed_allocations: usize,
+    pub bytes_reclaimed: usize,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventOp {
+    Allocate,
+    Deallocate,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AllocationEvent {
+    pub op: EventOp,
+    pub id: Uuid,
+    pub size_bytes: usize,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Bounds for `/events`, each either RFC 3339 or Unix epoch seconds.
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    #[serde(default, deserialize_with = "timestamp::deserialize")]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "timestamp::deserialize")]
+    pub until: Option<DateTime<Utc>>,
+}
+
+mod timestamp {
+    use chrono::{DateTime, Utc};
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+        let Some(value) = Option::<String>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+        if let Ok(secs) = value.parse::<i64>() {
+            return DateTime::from_timestamp(secs, 0)
+                .map(Some)
+                .ok_or_else(|| D::Error::custom(format!("timestamp {} is out of range", secs)));
+        }
+        DateTime::parse_from_rfc3339(&value)
+            .map(|timestamp| Some(timestamp.with_timezone(&Utc)))
+            .map_err(|e| D::Error::custom(format!("invalid timestamp {:?}: {}", value, e)))
+    }
+}
+
+/// Everything needed to recreate one allocation on another instance.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AllocationRecord {
+    pub id: Uuid,
+    pub size_bytes: usize,
+    pub created_at: DateTime<Utc>,
+    pub access_count: u64,
+    pub last_accessed_at: Option<DateTime<Utc>>,
+    pub ref_count: usize,
+    pub element_size: Option<usize>,
+    pub element_count: Option<usize>,
+    /// Base64 contents. Absent unless exported with `include_data=true`;
+    /// imported without it, the allocation starts zero-filled.
+    #[serde(default, with = "base64_data", skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<u8>>,
+}
+
+impl AllocationRecord {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let bad_request = |message: String| Err(AppError(StatusCode::BAD_REQUEST, message));
+        if self.ref_count == 0 {
+            return bad_request(format!("Allocation {} has a ref_count of 0", self.id));
+        }
+        if let Some(data) = &self.data {
+            if data.len() != self.size_bytes {
+                return bad_request(format!(
+                    "Allocation {} has {} bytes of data but a size of {}",
+                    self.id,
+                    data.len(),
+                    self.size_bytes
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+mod base64_data {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match data {
+            Some(bytes) => serializer.serialize_some(&STANDARD.encode(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|encoded| STANDARD.decode(encoded).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// Portable dump of an instance, produced by `/admin/export` and consumed
+/// by `/admin/import`. `config` and the totals are informational only;
+/// importing never changes the running config.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub exported_at: DateTime<Utc>,
+    pub config: Config,
+    pub total_allocated_bytes: usize,
+    pub active_allocations: usize,
+    pub allocations: Vec<AllocationRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub include_data: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    /// Replace existing allocations instead of refusing to import.
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportSummary {
+    pub imported_allocations: usize,
+    pub imported_bytes: usize,
+    pub replaced_allocations: usize,
+}
+
+#[derive(Debug)]
 pub struct AppError(pub StatusCode, pub String);
 
 impl IntoResponse for AppError {
@@ -35,3 +248,94 @@ impl IntoResponse for AppError {
         (self.0, self.1).into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Query, http::Uri};
+
+    fn events_query(query: &str) -> Result<EventsQuery, String> {
+        let uri: Uri = format!("/events?{}", query).parse().unwrap();
+        Query::<EventsQuery>::try_from_uri(&uri).map(|Query(q)| q).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn events_query_accepts_rfc3339_and_epoch_seconds() {
+        let expected = DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z").unwrap();
+        assert_eq!(events_query("since=2025-01-01T00:00:00Z").unwrap().since.unwrap(), expected);
+        assert_eq!(events_query("since=2025-01-01T01:00:00%2B01:00").unwrap().since.unwrap(), expected);
+        assert_eq!(events_query("until=1735689600").unwrap().until.unwrap(), expected);
+        assert!(events_query("").unwrap().since.is_none());
+        assert!(events_query("since=yesterday").is_err());
+    }
+
+    fn shape(size_bytes: Option<usize>, element_size: Option<usize>, element_count: Option<usize>) -> AllocateRequest {
+        AllocateRequest { size_bytes, element_size, element_count }
+    }
+
+    #[test]
+    fn resolve_size_from_element_shape() {
+        assert_eq!(shape(None, Some(8), Some(128)).resolve_size().unwrap(), 1024);
+        assert_eq!(
+            shape(None, Some(8), Some(128)).resolve_size().unwrap(),
+            AllocateRequest::with_size(1024).resolve_size().unwrap()
+        );
+        assert_eq!(shape(None, Some(0), Some(5)).resolve_size().unwrap(), 0);
+    }
+
+    #[test]
+    fn resolve_size_rejects_bad_shapes() {
+        let rejected = [
+            shape(None, Some(usize::MAX), Some(2)),
+            shape(Some(16), Some(8), Some(2)),
+            shape(Some(16), Some(8), None),
+            shape(None, Some(8), None),
+            shape(None, None, Some(8)),
+            shape(None, None, None),
+        ];
+        for request in rejected {
+            let err = request.resolve_size().unwrap_err();
+            assert_eq!(err.0, StatusCode::BAD_REQUEST, "{:?}", request);
+        }
+    }
+
+    fn search(min_size: Option<usize>, max_size: Option<usize>, min_age: Option<u64>, max_age: Option<u64>) -> SearchQuery {
+        SearchQuery { min_size, max_size, min_age, max_age }
+    }
+
+    fn info(size_bytes: usize, age_seconds: u64) -> AllocationInfo {
+        AllocationInfo {
+            id: Uuid::new_v4(),
+            size_bytes,
+            size_mb: size_bytes as f64 / 1_048_576.0,
+            age_seconds,
+            access_count: 0,
+            last_accessed_at: None,
+            ref_count: 1,
+            element_size: None,
+            element_count: None,
+        }
+    }
+
+    #[test]
+    fn search_rejects_inverted_ranges() {
+        assert_eq!(search(Some(10), Some(5), None, None).validate().unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(search(None, None, Some(10), Some(5)).validate().unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert!(search(Some(5), Some(5), Some(1), Some(1)).validate().is_ok());
+        assert!(search(None, None, None, None).validate().is_ok());
+    }
+
+    #[test]
+    fn search_matches_inclusive_bounds() {
+        let allocations = [info(100, 0), info(1024, 30), info(4096, 60), info(1 << 20, 3600)];
+        let sizes = |query: SearchQuery| -> Vec<usize> {
+            allocations.iter().filter(|alloc| query.matches(alloc)).map(|alloc| alloc.size_bytes).collect()
+        };
+
+        assert_eq!(sizes(search(None, None, None, None)), vec![100, 1024, 4096, 1 << 20]);
+        assert_eq!(sizes(search(Some(1024), Some(4096), None, None)), vec![1024, 4096]);
+        assert_eq!(sizes(search(None, None, Some(30), Some(60))), vec![1024, 4096]);
+        assert_eq!(sizes(search(Some(1024), None, None, Some(30))), vec![1024]);
+        assert_eq!(sizes(search(None, Some(99), None, None)), Vec::<usize>::new());
+    }
+}
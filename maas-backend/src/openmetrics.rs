@@ -0,0 +1,189 @@
+//! OpenMetrics text exposition for the default Prometheus registry.
+//!
+//! The `prometheus` crate only ships the legacy text and protobuf encoders,
+//! so this renders the same metric families in OpenMetrics 1.0 form: counter
+//! families drop their `_total` suffix while their samples carry it, untyped
+//! metrics are reported as `unknown`, and the output ends with `# EOF`.
+
+use std::fmt::Write;
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+
+pub const MEDIA_TYPE: &str = "application/openmetrics-text";
+pub const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Whether an `Accept` header value lists OpenMetrics among its media
+/// ranges without refusing it through `q=0`.
+pub fn is_accepted(accept: &str) -> bool {
+    accept.split(',').any(|range| {
+        let mut parts = range.split(';').map(str::trim);
+        let media_type = parts.next().unwrap_or_default();
+        media_type.eq_ignore_ascii_case(MEDIA_TYPE)
+            && !parts.any(|param| match param.split_once('=') {
+                Some((name, q)) if name.trim().eq_ignore_ascii_case("q") => {
+                    q.trim().parse::<f32>().is_ok_and(|q| q == 0.0)
+                }
+                _ => false,
+            })
+    })
+}
+
+pub fn encode(metric_families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in metric_families {
+        let metric_type = family.get_field_type();
+        let name = match metric_type {
+            MetricType::COUNTER => family.get_name().strip_suffix("_total").unwrap_or(family.get_name()),
+            _ => family.get_name(),
+        };
+        let type_name = match metric_type {
+            MetricType::COUNTER => "counter",
+            MetricType::GAUGE => "gauge",
+            MetricType::HISTOGRAM => "histogram",
+            MetricType::SUMMARY => "summary",
+            MetricType::UNTYPED => "unknown",
+        };
+
+        if !family.get_help().is_empty() {
+            let _ = writeln!(out, "# HELP {} {}", name, escape(family.get_help()));
+        }
+        let _ = writeln!(out, "# TYPE {} {}", name, type_name);
+
+        for metric in family.get_metric() {
+            match metric_type {
+                MetricType::COUNTER => {
+                    write_sample(&mut out, name, "_total", metric, None, metric.get_counter().get_value());
+                }
+                MetricType::GAUGE => {
+                    write_sample(&mut out, name, "", metric, None, metric.get_gauge().get_value());
+                }
+                MetricType::UNTYPED => {
+                    write_sample(&mut out, name, "", metric, None, metric.get_untyped().get_value());
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let mut inf_seen = false;
+                    for bucket in histogram.get_bucket() {
+                        let upper_bound = bucket.get_upper_bound();
+                        inf_seen |= upper_bound == f64::INFINITY;
+                        let le = format_value(upper_bound);
+                        write_sample(&mut out, name, "_bucket", metric, Some(("le", &le)), bucket.get_cumulative_count() as f64);
+                    }
+                    if !inf_seen {
+                        write_sample(&mut out, name, "_bucket", metric, Some(("le", "+Inf")), histogram.get_sample_count() as f64);
+                    }
+                    write_sample(&mut out, name, "_sum", metric, None, histogram.get_sample_sum());
+                    write_sample(&mut out, name, "_count", metric, None, histogram.get_sample_count() as f64);
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        let q = format_value(quantile.get_quantile());
+                        write_sample(&mut out, name, "", metric, Some(("quantile", &q)), quantile.get_value());
+                    }
+                    write_sample(&mut out, name, "_sum", metric, None, summary.get_sample_sum());
+                    write_sample(&mut out, name, "_count", metric, None, summary.get_sample_count() as f64);
+                }
+            }
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+fn write_sample(
+    out: &mut String,
+    name: &str,
+    suffix: &str,
+    metric: &Metric,
+    extra_label: Option<(&str, &str)>,
+    value: f64,
+) {
+    out.push_str(name);
+    out.push_str(suffix);
+    write_labels(out, metric.get_label(), extra_label);
+    out.push(' ');
+    out.push_str(&format_value(value));
+    // OpenMetrics timestamps are in seconds, not milliseconds.
+    let timestamp_ms = metric.get_timestamp_ms();
+    if timestamp_ms != 0 {
+        let _ = write!(out, " {}", timestamp_ms as f64 / 1000.0);
+    }
+    out.push('\n');
+}
+
+fn write_labels(out: &mut String, labels: &[LabelPair], extra_label: Option<(&str, &str)>) {
+    let pairs = labels
+        .iter()
+        .map(|label| (label.get_name(), label.get_value()))
+        .chain(extra_label);
+    let mut separator = '{';
+    for (name, value) in pairs {
+        let _ = write!(out, "{}{}=\"{}\"", separator, name, escape(value));
+        separator = ',';
+    }
+    if separator == ',' {
+        out.push('}');
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Counter, Gauge, Histogram, HistogramOpts, Opts, Registry};
+
+    fn registry() -> Registry {
+        let registry = Registry::new();
+        let counter = Counter::with_opts(Opts::new("jobs_total", "Jobs \"done\"")).unwrap();
+        counter.inc_by(3.0);
+        let gauge = Gauge::with_opts(Opts::new("queue_depth", "Depth").const_label("queue", "main")).unwrap();
+        gauge.set(2.5);
+        let histogram = Histogram::with_opts(HistogramOpts::new("latency_seconds", "Latency").buckets(vec![0.5, 1.0])).unwrap();
+        histogram.observe(0.25);
+        registry.register(Box::new(counter)).unwrap();
+        registry.register(Box::new(gauge)).unwrap();
+        registry.register(Box::new(histogram)).unwrap();
+        registry
+    }
+
+    #[test]
+    fn counters_drop_total_from_family_but_keep_it_on_samples() {
+        let out = encode(&registry().gather());
+        assert!(out.contains("# TYPE jobs counter\n"), "{}", out);
+        assert!(out.contains("# HELP jobs Jobs \\\"done\\\"\n"), "{}", out);
+        assert!(out.contains("\njobs_total 3\n"), "{}", out);
+    }
+
+    #[test]
+    fn gauges_and_histograms_are_rendered() {
+        let out = encode(&registry().gather());
+        assert!(out.contains("queue_depth{queue=\"main\"} 2.5\n"), "{}", out);
+        assert!(out.contains("latency_seconds_bucket{le=\"0.5\"} 1\n"), "{}", out);
+        assert!(out.contains("latency_seconds_bucket{le=\"+Inf\"} 1\n"), "{}", out);
+        assert!(out.contains("latency_seconds_count 1\n"), "{}", out);
+    }
+
+    #[test]
+    fn output_ends_with_eof() {
+        assert!(encode(&registry().gather()).ends_with("# EOF\n"));
+        assert_eq!(encode(&[]), "# EOF\n");
+    }
+}
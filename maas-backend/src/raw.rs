@@ -0,0 +1,394 @@
+//! Length-prefixed binary protocol for clients that want to skip HTTP.
+//!
+//! Every request frame is `[op:u8][len:u32 BE][payload]` and every response
+//! frame is `[status:u8][len:u32 BE][payload]`. Integers are big-endian and
+//! ids are the 16 raw bytes of the UUID.
+//!
+//! | op     | request payload                  | OK response payload |
+//! |--------|----------------------------------|---------------------|
+//! | `0x01` | allocate: `size:u64`             | `id`                |
+//! | `0x02` | deallocate: `id`                 | empty               |
+//! | `0x03` | read: `id offset:u64 len:u32`    | the bytes read      |
+//! | `0x04` | write: `id offset:u64 data...`   | empty               |
+//!
+//! Error responses carry a UTF-8 message. A frame with an unknown opcode or
+//! a malformed payload gets an error response and the connection stays
+//! open; a frame longer than `server.raw_max_frame_bytes` gets an error
+//! response and the connection is closed, since the stream can no longer
+//! be trusted to be in sync.
+
+use std::io;
+use axum::http::StatusCode;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+use uuid::Uuid;
+use crate::{
+    handlers::{allocate, deallocate, REQUEST_COUNTER},
+    models::{AllocateRequest, AppError},
+    state::AppState,
+};
+
+pub const OP_ALLOCATE: u8 = 0x01;
+pub const OP_DEALLOCATE: u8 = 0x02;
+pub const OP_READ: u8 = 0x03;
+pub const OP_WRITE: u8 = 0x04;
+
+pub const STATUS_OK: u8 = 0x00;
+pub const STATUS_BAD_REQUEST: u8 = 0x01;
+pub const STATUS_NOT_FOUND: u8 = 0x02;
+pub const STATUS_FRAME_TOO_LARGE: u8 = 0x03;
+pub const STATUS_INTERNAL_ERROR: u8 = 0x04;
+
+const HEADER_LEN: usize = 5;
+const ID_LEN: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RawRequest<'a> {
+    Allocate { size_bytes: u64 },
+    Deallocate { id: Uuid },
+    Read { id: Uuid, offset: u64, len: u32 },
+    Write { id: Uuid, offset: u64, data: &'a [u8] },
+}
+
+/// Decodes a frame payload. Never panics, whatever the input.
+pub fn decode_request(op: u8, payload: &[u8]) -> Result<RawRequest<'_>, String> {
+    let mut cursor = Cursor(payload);
+    let request = match op {
+        OP_ALLOCATE => RawRequest::Allocate { size_bytes: cursor.u64()? },
+        OP_DEALLOCATE => RawRequest::Deallocate { id: cursor.id()? },
+        OP_READ => RawRequest::Read { id: cursor.id()?, offset: cursor.u64()?, len: cursor.u32()? },
+        OP_WRITE => {
+            let id = cursor.id()?;
+            let offset = cursor.u64()?;
+            return Ok(RawRequest::Write { id, offset, data: cursor.0 });
+        }
+        _ => return Err(format!("Unknown opcode 0x{:02x}", op)),
+    };
+    if !cursor.0.is_empty() {
+        return Err(format!("{} trailing bytes in payload", cursor.0.len()));
+    }
+    Ok(request)
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < n {
+            return Err("Payload too short".to_string());
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn id(&mut self) -> Result<Uuid, String> {
+        Ok(Uuid::from_bytes(self.take(ID_LEN)?.try_into().unwrap()))
+    }
+}
+
+fn execute(state: &AppState, request: RawRequest<'_>) -> Result<Vec<u8>, AppError> {
+    let too_large = || AppError(StatusCode::BAD_REQUEST, "Value does not fit in usize".to_string());
+    match request {
+        RawRequest::Allocate { size_bytes } => {
+            let size_bytes = usize::try_from(size_bytes).map_err(|_| too_large())?;
+            let info = allocate(state, AllocateRequest::with_size(size_bytes))?;
+            Ok(info.id.as_bytes().to_vec())
+        }
+        RawRequest::Deallocate { id } => deallocate(state, id).map(|()| Vec::new()),
+        RawRequest::Read { id, offset, len } => {
+            let offset = usize::try_from(offset).map_err(|_| too_large())?;
+            state.allocator.read(id, offset, len as usize)
+        }
+        RawRequest::Write { id, offset, data } => {
+            let offset = usize::try_from(offset).map_err(|_| too_large())?;
+            state.allocator.write(id, offset, data).map(|()| Vec::new())
+        }
+    }
+}
+
+fn status_for(err: &AppError) -> u8 {
+    match err.0 {
+        StatusCode::BAD_REQUEST => STATUS_BAD_REQUEST,
+        StatusCode::NOT_FOUND => STATUS_NOT_FOUND,
+        _ => STATUS_INTERNAL_ERROR,
+    }
+}
+
+async fn write_frame(stream: &mut TcpStream, status: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.push(status);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame).await
+}
+
+/// Serves each connection on its own task until `shutdown` turns true.
+/// Shutdown stops accepting, closes every connection at its next frame
+/// boundary and returns once all of them are gone, so a frame that was
+/// already being read when draining began has started its allocation by
+/// then.
+pub async fn serve(listener: TcpListener, state: AppState, mut shutdown: watch::Receiver<bool>) {
+    let mut connections = JoinSet::new();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            // Reap finished connections so the set doesn't grow unbounded.
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            _ = stopped(&mut shutdown) => break,
+        };
+        match accepted {
+            Ok((stream, peer)) => {
+                let state = state.clone();
+                let shutdown = shutdown.clone();
+                connections.spawn(async move {
+                    if let Err(e) = handle_connection(state, stream, shutdown).await {
+                        warn!(%peer, "raw protocol connection error: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("raw protocol accept failed: {}", e),
+        }
+    }
+
+    drop(listener);
+    while connections.join_next().await.is_some() {}
+}
+
+/// Resolves once shutdown is requested or the sender is gone.
+async fn stopped(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|&stop| stop).await;
+}
+
+async fn handle_connection(
+    state: AppState,
+    mut stream: TcpStream,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let max_frame = state.config.server.raw_max_frame_bytes;
+    loop {
+        let mut header = [0u8; HEADER_LEN];
+        let read = tokio::select! {
+            read = stream.read_exact(&mut header) => read,
+            _ = stopped(&mut shutdown) => return stream.shutdown().await,
+        };
+        match read {
+            Ok(_) => {}
+            // Clean disconnect between frames.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        REQUEST_COUNTER.inc();
+
+        let op = header[0];
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+        if len > max_frame {
+            let message = format!("Frame of {} bytes exceeds limit of {}", len, max_frame);
+            info!("raw protocol: {}, closing connection", message);
+            write_frame(&mut stream, STATUS_FRAME_TOO_LARGE, message.as_bytes()).await?;
+            return stream.shutdown().await;
+        }
+
+        let mut payload = vec![0u8; len as usize];
+        stream.read_exact(&mut payload).await?;
+
+        let result = decode_request(op, &payload)
+            .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+            .and_then(|request| execute(&state, request));
+        match result {
+            Ok(body) => write_frame(&mut stream, STATUS_OK, &body).await?,
+            Err(err) => write_frame(&mut stream, status_for(&err), err.1.as_bytes()).await?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn payload(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    fn run(state: &AppState, op: u8, payload: &[u8]) -> Result<Vec<u8>, AppError> {
+        decode_request(op, payload)
+            .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))
+            .and_then(|request| execute(state, request))
+    }
+
+    #[test]
+    fn decodes_well_formed_frames() {
+        let id = Uuid::new_v4();
+        assert_eq!(
+            decode_request(OP_ALLOCATE, &64u64.to_be_bytes()),
+            Ok(RawRequest::Allocate { size_bytes: 64 })
+        );
+        assert_eq!(decode_request(OP_DEALLOCATE, id.as_bytes()), Ok(RawRequest::Deallocate { id }));
+        assert_eq!(
+            decode_request(OP_READ, &payload(&[id.as_bytes(), &8u64.to_be_bytes(), &4u32.to_be_bytes()])),
+            Ok(RawRequest::Read { id, offset: 8, len: 4 })
+        );
+        assert_eq!(
+            decode_request(OP_WRITE, &payload(&[id.as_bytes(), &2u64.to_be_bytes(), b"abc"])),
+            Ok(RawRequest::Write { id, offset: 2, data: b"abc" })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        assert!(decode_request(0x7f, &[]).is_err());
+        assert!(decode_request(OP_ALLOCATE, &[0; 7]).is_err());
+        assert!(decode_request(OP_ALLOCATE, &[0; 9]).is_err());
+        assert!(decode_request(OP_READ, &[0; ID_LEN]).is_err());
+    }
+
+    #[test]
+    fn decode_never_panics_on_random_input() {
+        // xorshift64, so failures are reproducible without a rand dependency.
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for _ in 0..20_000 {
+            let op = (next() % 6) as u8;
+            let len = (next() % 48) as usize;
+            let payload: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            let _ = decode_request(op, &payload);
+        }
+    }
+
+    #[test]
+    fn allocate_write_read_deallocate_round_trip() {
+        let state = AppState::new(Config::default());
+        let id = run(&state, OP_ALLOCATE, &16u64.to_be_bytes()).unwrap();
+        assert_eq!(id.len(), ID_LEN);
+
+        let written = run(&state, OP_WRITE, &payload(&[&id, &4u64.to_be_bytes(), b"raw!"])).unwrap();
+        assert!(written.is_empty());
+        let read = run(&state, OP_READ, &payload(&[&id, &4u64.to_be_bytes(), &4u32.to_be_bytes()])).unwrap();
+        assert_eq!(read, b"raw!");
+
+        assert!(run(&state, OP_DEALLOCATE, &id).unwrap().is_empty());
+        let err = run(&state, OP_DEALLOCATE, &id).unwrap_err();
+        assert_eq!(status_for(&err), STATUS_NOT_FOUND);
+    }
+
+    async fn send_frame(stream: &mut TcpStream, op: u8, payload: &[u8]) -> io::Result<(u8, Vec<u8>)> {
+        let mut frame = vec![op];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        stream.write_all(&frame).await?;
+        let mut header = [0u8; HEADER_LEN];
+        stream.read_exact(&mut header).await?;
+        let mut body = vec![0u8; u32::from_be_bytes(header[1..].try_into().unwrap()) as usize];
+        stream.read_exact(&mut body).await?;
+        Ok((header[0], body))
+    }
+
+    /// Runs `handle_connection` on the server side of a fresh loopback
+    /// connection. The returned sender keeps the connection from seeing a
+    /// shutdown.
+    async fn connect(
+        state: AppState,
+    ) -> (TcpStream, tokio::task::JoinHandle<io::Result<()>>, watch::Sender<bool>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let connection = tokio::spawn(handle_connection(state, stream, shutdown_rx));
+        (client, connection, shutdown_tx)
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_is_rejected_and_closes_connection() {
+        let mut config = Config::default();
+        config.server.raw_max_frame_bytes = 16;
+        let (mut client, connection, _shutdown_tx) = connect(AppState::new(config)).await;
+
+        // Only the header: the server must answer without reading a payload.
+        let mut header = vec![OP_WRITE];
+        header.extend_from_slice(&17u32.to_be_bytes());
+        client.write_all(&header).await.unwrap();
+
+        let mut response = [0u8; HEADER_LEN];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response[0], STATUS_FRAME_TOO_LARGE);
+        let len = u32::from_be_bytes([response[1], response[2], response[3], response[4]]);
+        let mut message = vec![0u8; len as usize];
+        client.read_exact(&mut message).await.unwrap();
+        assert!(String::from_utf8(message).unwrap().contains("exceeds limit of 16"));
+
+        assert_eq!(client.read(&mut [0u8; 1]).await.unwrap(), 0);
+        connection.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn unknown_opcode_gets_error_and_connection_stays_usable() {
+        let state = AppState::new(Config::default());
+        let (mut client, connection, shutdown_tx) = connect(state.clone()).await;
+
+        let (status, message) = send_frame(&mut client, 0x7f, b"junk").await.unwrap();
+        assert_eq!(status, STATUS_BAD_REQUEST);
+        assert!(!message.is_empty());
+
+        let (status, id) = send_frame(&mut client, OP_ALLOCATE, &8u64.to_be_bytes()).await.unwrap();
+        assert_eq!(status, STATUS_OK);
+        assert_eq!(id.len(), ID_LEN);
+        assert_eq!(state.allocator.get_active_allocations(), 1);
+
+        drop(client);
+        connection.await.unwrap().unwrap();
+        drop(shutdown_tx);
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_accepting_and_closes_connections() {
+        let state = AppState::new(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let server = tokio::spawn(serve(listener, state.clone(), shutdown_rx));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (status, _) = send_frame(&mut client, OP_ALLOCATE, &8u64.to_be_bytes()).await.unwrap();
+        assert_eq!(status, STATUS_OK);
+
+        shutdown_tx.send(true).unwrap();
+        server.await.unwrap();
+        assert!(send_frame(&mut client, OP_ALLOCATE, &8u64.to_be_bytes()).await.is_err());
+        assert!(TcpStream::connect(addr).await.is_err());
+        assert_eq!(state.allocator.get_active_allocations(), 1);
+    }
+
+    #[test]
+    fn oversized_allocations_are_errors_not_aborts() {
+        let state = AppState::new(Config::default());
+        let err = run(&state, OP_ALLOCATE, &(1u64 << 50).to_be_bytes()).unwrap_err();
+        assert_eq!(status_for(&err), STATUS_BAD_REQUEST);
+
+        // With the limit lifted, the failed allocation itself must surface.
+        let mut config = Config::default();
+        config.memory.max_allocation_bytes = usize::MAX;
+        let state = AppState::new(config);
+        for size in [1u64 << 50, u64::MAX] {
+            let err = run(&state, OP_ALLOCATE, &size.to_be_bytes()).unwrap_err();
+            assert_eq!(status_for(&err), STATUS_INTERNAL_ERROR);
+        }
+        assert_eq!(state.allocator.get_active_allocations(), 0);
+    }
+}
@@ -0,0 +1,258 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+use crate::{
+    handlers::{allocate, deallocate, REQUEST_COUNTER},
+    models::{AllocateRequest, AppError},
+    state::AppState,
+};
+
+// Error codes defined by the JSON-RPC 2.0 spec.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+// Implementation-defined server errors (-32000 to -32099).
+const SERVER_ERROR: i64 = -32000;
+const NOT_FOUND: i64 = -32001;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    /// `None` only when the member is absent; `"id": null` is `Some(Null)`
+    /// and still gets a response.
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+}
+
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), data: None }
+    }
+}
+
+impl From<AppError> for RpcError {
+    fn from(err: AppError) -> Self {
+        let code = match err.0 {
+            StatusCode::BAD_REQUEST => INVALID_PARAMS,
+            StatusCode::NOT_FOUND => NOT_FOUND,
+            StatusCode::INTERNAL_SERVER_ERROR => INTERNAL_ERROR,
+            _ => SERVER_ERROR,
+        };
+        Self {
+            code,
+            message: err.1,
+            data: Some(serde_json::json!({ "http_status": err.0.as_u16() })),
+        }
+    }
+}
+
+impl RpcResponse {
+    fn result(id: Value, result: Value) -> Self {
+        Self { jsonrpc: "2.0", result: Some(result), error: None, id }
+    }
+
+    fn error(id: Value, error: RpcError) -> Self {
+        Self { jsonrpc: "2.0", result: None, error: Some(error), id }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeallocateParams {
+    id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadParams {
+    id: Uuid,
+    offset: usize,
+    len: usize,
+}
+
+/// JSON-RPC 2.0 endpoint exposing `allocate`, `deallocate`, `read` and
+/// `stats`. `read` returns the bytes base64-encoded.
+/// Accepts single requests and batches; notifications get no response.
+pub async fn rpc_handler(State(state): State<AppState>, body: Bytes) -> Response {
+    REQUEST_COUNTER.inc();
+
+    let value: Value = match serde_json::from_slice(&body) {
+        Ok(value) => value,
+        Err(e) => {
+            let error = RpcError::new(PARSE_ERROR, format!("Parse error: {}", e));
+            return Json(RpcResponse::error(Value::Null, error)).into_response();
+        }
+    };
+
+    match value {
+        Value::Array(batch) if batch.is_empty() => {
+            let error = RpcError::new(INVALID_REQUEST, "Invalid Request: empty batch");
+            Json(RpcResponse::error(Value::Null, error)).into_response()
+        }
+        Value::Array(batch) => {
+            let responses: Vec<RpcResponse> = batch
+                .into_iter()
+                .filter_map(|call| handle_call(&state, call))
+                .collect();
+            if responses.is_empty() {
+                StatusCode::NO_CONTENT.into_response()
+            } else {
+                Json(responses).into_response()
+            }
+        }
+        call => match handle_call(&state, call) {
+            Some(response) => Json(response).into_response(),
+            None => StatusCode::NO_CONTENT.into_response(),
+        },
+    }
+}
+
+/// Handles one call. Returns `None` for notifications (requests without an id).
+fn handle_call(state: &AppState, call: Value) -> Option<RpcResponse> {
+    let request: RpcRequest = match serde_json::from_value(call) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = RpcError::new(INVALID_REQUEST, format!("Invalid Request: {}", e));
+            return Some(RpcResponse::error(Value::Null, error));
+        }
+    };
+    if request.jsonrpc != "2.0" {
+        let error = RpcError::new(INVALID_REQUEST, "Invalid Request: jsonrpc must be \"2.0\"");
+        return Some(RpcResponse::error(request.id.unwrap_or(Value::Null), error));
+    }
+
+    let outcome = dispatch(state, &request.method, request.params);
+    let id = request.id?;
+    Some(match outcome {
+        Ok(result) => RpcResponse::result(id, result),
+        Err(error) => RpcResponse::error(id, error),
+    })
+}
+
+fn dispatch(state: &AppState, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "allocate" => {
+            let payload: AllocateRequest = parse_params(params)?;
+            let info = allocate(state, payload)?;
+            Ok(serde_json::to_value(info).unwrap())
+        }
+        "deallocate" => {
+            let DeallocateParams { id } = parse_params(params)?;
+            deallocate(state, id)?;
+            Ok(Value::Bool(true))
+        }
+        "read" => {
+            let ReadParams { id, offset, len } = parse_params(params)?;
+            let bytes = state.allocator.read(id, offset, len)?;
+            Ok(Value::String(STANDARD.encode(bytes)))
+        }
+        "stats" => Ok(serde_json::to_value(state.get_stats()).unwrap()),
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params)
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::config::Config;
+
+    async fn call(state: &AppState, body: Value) -> (StatusCode, Value) {
+        let response = rpc_handler(State(state.clone()), Bytes::from(body.to_string())).await;
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let value = if bytes.is_empty() { Value::Null } else { serde_json::from_slice(&bytes).unwrap() };
+        (status, value)
+    }
+
+    #[tokio::test]
+    async fn allocate_then_read() {
+        let state = AppState::new(Config::default());
+        let (_, response) = call(&state, json!({"jsonrpc": "2.0", "method": "allocate", "params": {"size_bytes": 4}, "id": 1})).await;
+        assert_eq!(response["id"], 1);
+        let id: Uuid = serde_json::from_value(response["result"]["id"].clone()).unwrap();
+        state.allocator.write(id, 0, b"rpc!").unwrap();
+
+        let (_, response) = call(&state, json!({"jsonrpc": "2.0", "method": "read", "params": {"id": id, "offset": 0, "len": 4}, "id": 2})).await;
+        assert_eq!(response["result"], STANDARD.encode(b"rpc!"));
+    }
+
+    #[tokio::test]
+    async fn app_errors_are_mapped_to_rpc_codes() {
+        let state = AppState::new(Config::default());
+        let (_, response) = call(&state, json!({"jsonrpc": "2.0", "method": "deallocate", "params": {"id": Uuid::new_v4()}, "id": 1})).await;
+        assert_eq!(response["error"]["code"], NOT_FOUND);
+        assert_eq!(response["error"]["data"]["http_status"], 404);
+
+        let (_, response) = call(&state, json!({"jsonrpc": "2.0", "method": "allocate", "params": {}, "id": 2})).await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn null_id_is_a_call_not_a_notification() {
+        let state = AppState::new(Config::default());
+        let (status, response) = call(&state, json!({"jsonrpc": "2.0", "method": "stats", "id": null})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["id"], Value::Null);
+        assert_eq!(response["result"]["active_allocations"], 0);
+
+        let (status, _) = call(&state, json!({"jsonrpc": "2.0", "method": "stats"})).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn batches_skip_notifications() {
+        let state = AppState::new(Config::default());
+        let (status, response) = call(&state, json!([
+            {"jsonrpc": "2.0", "method": "allocate", "params": {"size_bytes": 1}, "id": "a"},
+            {"jsonrpc": "2.0", "method": "allocate", "params": {"size_bytes": 1}},
+            {"jsonrpc": "2.0", "method": "nope", "id": "b"},
+        ])).await;
+        assert_eq!(status, StatusCode::OK);
+        let responses = response.as_array().unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["id"], "a");
+        assert_eq!(responses[1]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(state.allocator.get_active_allocations(), 2);
+
+        let (status, _) = call(&state, json!([{"jsonrpc": "2.0", "method": "stats"}])).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+}
@@ -1,52 +1,201 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use uuid::Uuid;
-use std::time::SystemTime;
-use crate::models::{AllocationInfo, MemoryStats};
-
-#[derive(Debug)]
-pub struct MemoryAllocation {
-    pub id: Uuid,
-    pub size_bytes: usize,
-    pub data: Arc<Vec<u8>>,
-    pub created_at: SystemTime,
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tracing::warn;
+use crate::allocator::{Allocator, BufferAllocator};
+use crate::config::Config;
+use crate::models::{AllocationEvent, AllocationInfo, EventOp, MemoryStats};
+
+/// Locks `mutex`, taking the data back if a handler panicked while holding
+/// it. Every critical section in this service leaves the protected maps
+/// consistent between statements, so a poisoned lock is still safe to use
+/// and recovering keeps one panicked request from failing every later one.
+/// The poison flag is cleared so the warning is logged once per panic.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        warn!("recovering poisoned lock");
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
 }
 
 #[derive(Clone)]
 pub struct AppState {
-    pub allocations: Arc<Mutex<HashMap<Uuid, MemoryAllocation>>>,
+    pub allocator: Arc<dyn Allocator + Send + Sync>,
+    /// Ring buffer of the most recent allocation events, oldest first.
+    pub events: Arc<Mutex<VecDeque<AllocationEvent>>>,
+    /// Allocations currently being made; shutdown waits for this to drain.
+    pub in_flight: Arc<AtomicUsize>,
+    pub config: Arc<Config>,
+}
+
+/// Counts an in-flight allocation for as long as it is alive, including
+/// when the allocation returns early with an error.
+pub struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl AppState {
-    pub fn new() -> Self {
+    pub fn new(config: Config) -> Self {
+        let allocator = Arc::new(BufferAllocator::new(config.memory.clone()));
+        Self::with_allocator(config, allocator)
+    }
+
+    /// State backed by a custom allocator instead of the default `BufferAllocator`.
+    pub fn with_allocator(config: Config, allocator: Arc<dyn Allocator + Send + Sync>) -> Self {
         Self {
-            allocations: Arc::new(Mutex::new(HashMap::new())),
+            allocator,
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(config.server.event_buffer_size))),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            config: Arc::new(config),
+        }
+    }
+
+    pub fn begin_in_flight(&self) -> InFlightGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.in_flight.clone())
+    }
+
+    /// Waits until no allocation is in flight, giving up after `timeout`.
+    /// Returns whether the counter reached zero.
+    pub async fn drain_in_flight(&self, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.in_flight.load(Ordering::SeqCst) > 0 {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
+        true
     }
 
     pub fn get_stats(&self) -> MemoryStats {
-        let allocations = self.allocations.lock().unwrap();
-        let mut total_bytes = 0;
-        let mut allocation_infos = Vec::new();
-        let now = SystemTime::now();
-
-        for alloc in allocations.values() {
-            total_bytes += alloc.size_bytes;
-            let age = now.duration_since(alloc.created_at).unwrap_or_default().as_secs();
-            
-            allocation_infos.push(AllocationInfo {
-                id: alloc.id,
-                size_bytes: alloc.size_bytes,
-                size_mb: alloc.size_bytes as f64 / 1_048_576.0,
-                age_seconds: age,
-            });
+        self.allocator.get_stats()
+    }
+
+    /// Allocations whose info satisfies `predicate`.
+    pub fn find_allocations(&self, predicate: impl Fn(&AllocationInfo) -> bool) -> Vec<AllocationInfo> {
+        self.allocator
+            .allocations()
+            .into_iter()
+            .filter(|info| predicate(info))
+            .collect()
+    }
+
+    /// Allocations that have been alive for at least `min_age`.
+    pub fn allocations_older_than(&self, min_age: Duration) -> Vec<AllocationInfo> {
+        self.find_allocations(|info| info.age_seconds >= min_age.as_secs())
+    }
+
+    /// Appends an event, evicting the oldest one once the buffer is full.
+    pub fn record_event(&self, op: EventOp, id: Uuid, size_bytes: usize) {
+        let capacity = self.config.server.event_buffer_size;
+        if capacity == 0 {
+            return;
         }
 
-        MemoryStats {
-            total_allocated_bytes: total_bytes,
-            total_allocated_mb: total_bytes as f64 / 1_048_576.0,
-            active_allocations: allocations.len(),
-            allocations: allocation_infos,
+        let mut events = lock(&self.events);
+        while events.len() >= capacity {
+            events.pop_front();
         }
+        events.push_back(AllocationEvent {
+            op,
+            id,
+            size_bytes,
+            timestamp: Utc::now(),
+        });
+    }
+
+    /// Buffered events with `since <= timestamp <= until`; omitted bounds are open.
+    pub fn events_between(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Vec<AllocationEvent> {
+        let events = lock(&self.events);
+        events
+            .iter()
+            .filter(|event| since.is_none_or(|since| event.timestamp >= since))
+            .filter(|event| until.is_none_or(|until| event.timestamp <= until))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_with_buffer(event_buffer_size: usize) -> AppState {
+        let mut config = Config::default();
+        config.server.event_buffer_size = event_buffer_size;
+        AppState::new(config)
+    }
+
+    #[test]
+    fn full_event_buffer_evicts_oldest() {
+        let state = state_with_buffer(2);
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for (size, id) in ids.iter().enumerate() {
+            state.record_event(EventOp::Allocate, *id, size);
+        }
+
+        let events = state.events_between(None, None);
+        assert_eq!(events.iter().map(|event| event.id).collect::<Vec<_>>(), ids[1..]);
+    }
+
+    #[test]
+    fn zero_sized_buffer_records_nothing() {
+        let state = state_with_buffer(0);
+        state.record_event(EventOp::Allocate, Uuid::new_v4(), 1);
+        assert!(state.events_between(None, None).is_empty());
+    }
+
+    #[test]
+    fn events_between_filters_inclusive_sub_range() {
+        let state = state_with_buffer(16);
+        for size in 0..4 {
+            state.record_event(EventOp::Allocate, Uuid::new_v4(), size);
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        let all = state.events_between(None, None);
+
+        let middle = state.events_between(Some(all[1].timestamp), Some(all[2].timestamp));
+        assert_eq!(middle.iter().map(|event| event.size_bytes).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(state.events_between(Some(all[3].timestamp), None).len(), 1);
+        assert_eq!(state.events_between(None, Some(all[0].timestamp)).len(), 1);
+    }
+
+    #[test]
+    fn poisoned_lock_is_recovered_once() {
+        let (logs, _guard) = crate::test_util::capture_logs(tracing::Level::WARN);
+        let mutex = Arc::new(Mutex::new(0));
+        let poisoner = mutex.clone();
+        let _ = std::thread::spawn(move || {
+            let _held = poisoner.lock().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(mutex.is_poisoned());
+
+        *lock(&mutex) += 1;
+        *lock(&mutex) += 1;
+        assert_eq!(*lock(&mutex), 2);
+        assert!(!mutex.is_poisoned());
+        assert_eq!(logs.contents().matches("recovering poisoned lock").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn drain_gives_up_after_timeout() {
+        let state = AppState::new(Config::default());
+        let _stuck = state.begin_in_flight();
+        assert!(!state.drain_in_flight(Duration::from_millis(20)).await);
     }
 }
@@ -0,0 +1,127 @@
+//! Helpers shared by the unit tests.
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use axum::http::StatusCode;
+use tracing::subscriber::DefaultGuard;
+use tracing::Level;
+use uuid::Uuid;
+use crate::{
+    allocator::{checked_range, Allocator, Deallocation},
+    models::{AllocateRequest, AllocationInfo, AllocationRecord, AppError},
+};
+
+/// Log output captured by [`capture_logs`].
+#[derive(Clone, Default)]
+pub struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Logs {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Records every event at `level` or above on the current thread until the
+/// guard is dropped. Async tests must use the current-thread runtime.
+pub fn capture_logs(level: Level) -> (Logs, DefaultGuard) {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    (logs, tracing::subscriber::set_default(subscriber))
+}
+
+/// Bookkeeping-only allocator: remembers sizes, stores no bytes.
+/// `delay` makes every allocation that slow.
+#[derive(Default)]
+pub struct MockAllocator {
+    pub allocations: std::sync::Mutex<Vec<AllocationInfo>>,
+    pub delay: Duration,
+}
+
+impl MockAllocator {
+    fn not_found() -> AppError {
+        AppError(StatusCode::NOT_FOUND, "Allocation not found".to_string())
+    }
+}
+
+impl Allocator for MockAllocator {
+    fn allocate(&self, request: &AllocateRequest) -> Result<AllocationInfo, AppError> {
+        thread::sleep(self.delay);
+        let size_bytes = request.resolve_size()?;
+        let info = AllocationInfo {
+            id: Uuid::new_v4(),
+            size_bytes,
+            size_mb: size_bytes as f64 / 1_048_576.0,
+            age_seconds: 0,
+            access_count: 0,
+            last_accessed_at: None,
+            ref_count: 1,
+            element_size: request.element_size,
+            element_count: request.element_count,
+        };
+        self.allocations.lock().unwrap().push(info.clone());
+        Ok(info)
+    }
+
+    fn allocate_with_data(&self, bytes: &[u8]) -> Result<AllocationInfo, AppError> {
+        self.allocate(&AllocateRequest::with_size(bytes.len()))
+    }
+
+    fn deallocate(&self, id: Uuid) -> Result<Deallocation, AppError> {
+        let mut allocations = self.allocations.lock().unwrap();
+        let index = allocations.iter().position(|info| info.id == id).ok_or_else(Self::not_found)?;
+        let removed = allocations.remove(index);
+        Ok(Deallocation::Freed { size_bytes: removed.size_bytes })
+    }
+
+    fn read(&self, id: Uuid, offset: usize, len: usize) -> Result<Vec<u8>, AppError> {
+        checked_range(offset, len, self.size_of(id)?)?;
+        Ok(vec![0; len])
+    }
+
+    fn write_chunk(&self, id: Uuid, offset: usize, bytes: &[u8]) -> Result<(), AppError> {
+        checked_range(offset, bytes.len(), self.size_of(id)?).map(|_| ())
+    }
+
+    fn finish_write(&self, id: Uuid) -> Result<(), AppError> {
+        self.size_of(id).map(|_| ())
+    }
+
+    fn size_of(&self, id: Uuid) -> Result<usize, AppError> {
+        let allocations = self.allocations.lock().unwrap();
+        allocations.iter().find(|info| info.id == id).map(|info| info.size_bytes).ok_or_else(Self::not_found)
+    }
+
+    fn allocations(&self) -> Vec<AllocationInfo> {
+        self.allocations.lock().unwrap().clone()
+    }
+
+    fn flush(&self) -> Vec<AllocationInfo> {
+        std::mem::take(&mut *self.allocations.lock().unwrap())
+    }
+
+    fn export(&self, _include_data: bool) -> Vec<AllocationRecord> {
+        Vec::new()
+    }
+
+    fn import(&self, _records: Vec<AllocationRecord>, _replace: bool) -> Result<Vec<AllocationInfo>, AppError> {
+        Err(AppError(StatusCode::NOT_IMPLEMENTED, "Import not supported".to_string()))
+    }
+}